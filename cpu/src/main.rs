@@ -1,16 +1,24 @@
-use anyhow::Result;
-use humantime::Duration;
-use std::{fs, str};
+use anyhow::{anyhow, Context, Error, Result};
+use std::fs;
+use std::path::PathBuf;
+use std::str::{FromStr, SplitWhitespace};
+use std::time::Instant;
 use structopt::StructOpt;
-use waybar::{History, Loop, Output, Percentage};
+use waybar::{
+    Cgroup, Cluster, CommonOpt, Event, History, Output, Percentage, Recovery, Renderer, Style,
+    Template, Topology, Vars,
+};
+
+mod processes;
+use processes::Processes;
 
 #[derive(Debug, StructOpt)]
 struct Opt {
     #[structopt(short, long, default_value = "cpu", help = "CSS class")]
     class: String,
 
-    #[structopt(short, long, default_value = "1s", help = "interval between updates")]
-    interval: Duration,
+    #[structopt(flatten)]
+    common: CommonOpt,
 
     #[structopt(
         short,
@@ -20,73 +28,531 @@ struct Opt {
         help = "enable sparkline with N historical values"
     )]
     sparkline: u8,
+
+    #[structopt(
+        long,
+        default_value = "usage",
+        help = "what to report: usage, or load (the load average relative to the number of cores)"
+    )]
+    mode: Mode,
+
+    #[structopt(
+        long,
+        help = "report the busiest core's usage instead of the overall usage"
+    )]
+    max_core: bool,
+
+    #[structopt(
+        long,
+        name = "CLUSTER",
+        help = "report the usage of a single cluster of cores, such as p or e on a hybrid CPU"
+    )]
+    cluster: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "0",
+        name = "TOP",
+        help = "list the TOP processes by CPU usage in the tooltip"
+    )]
+    top: usize,
+
+    #[structopt(
+        long,
+        name = "IOWAIT",
+        help = "add the iowait class when the percentage of time waiting for I/O reaches IOWAIT"
+    )]
+    iowait_threshold: Option<u8>,
+
+    #[structopt(
+        long,
+        name = "STEAL",
+        help = "add the steal class when the percentage of time stolen by the hypervisor reaches STEAL"
+    )]
+    steal_threshold: Option<u8>,
+
+    #[structopt(
+        long,
+        name = "CGROUP",
+        help = "report usage against the quota of a cgroup v2 cgroup [default: this process's cgroup]"
+    )]
+    cgroup: Option<Option<PathBuf>>,
+
+    #[structopt(
+        long,
+        default_value = "/sys/devices/system/cpu",
+        help = "base path to the CPU sysfs, which is used to group cores into clusters"
+    )]
+    sysfs_cpu_path: PathBuf,
+
+    #[structopt(
+        long,
+        default_value = "{sparkline}",
+        help = "format for the module text"
+    )]
+    format: Template,
+
+    #[structopt(long, help = "format for the tooltip [default: depends on the mode]")]
+    tooltip_format: Option<Template>,
+}
+
+const USAGE_TOOLTIP: &str = "{percentage}%\n\n{breakdown}\n\n{core_table}";
+const CGROUP_TOOLTIP: &str = "{percentage}% of {cgroup_cpus:.1} CPUs in {cgroup}";
+const LOAD_TOOLTIP: &str =
+    "Load: {load1:.2}, {load5:.2}, {load15:.2}\n{running} running, {blocked} blocked";
+
+/// Mode selects what the module reports.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Mode {
+    Usage,
+    Load,
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "usage" => Ok(Mode::Usage),
+            "load" => Ok(Mode::Load),
+            _ => Err(anyhow!("unknown mode {}: expected usage or load", s)),
+        }
+    }
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
-    let classes = vec![opt.class.into()];
+    let classes = vec![opt.class];
 
     // Set up the history. We'll constrain the sparkline to a maximum of 10
     // historical values.
     let mut history = History::new(opt.sparkline.min(10) as usize);
+    let style = opt.common.style;
+    let format = opt.format;
+    let mode = opt.mode;
+    let top = opt.top;
+    let cgroup = match opt.cgroup {
+        Some(Some(path)) => Some(Cgroup::new(path)?),
+        Some(None) => Some(Cgroup::own()?),
+        None => None,
+    };
+    let tooltip_format = match opt.tooltip_format {
+        Some(template) => template,
+        None => {
+            let default = match (mode, &cgroup) {
+                (Mode::Usage, None) => USAGE_TOOLTIP,
+                (Mode::Usage, Some(_)) => CGROUP_TOOLTIP,
+                (Mode::Load, _) => LOAD_TOOLTIP,
+            };
+
+            if top > 0 {
+                format!("{}\n\n{{top}}", default).parse()?
+            } else {
+                default.parse()?
+            }
+        }
+    };
+    let mut thresholds = opt.common.thresholds();
+    let mut gaps = opt.common.gaps();
     let mut last: Option<Snapshot> = None;
+    let mut last_processes: Option<Processes> = None;
+    let mut shown: Option<(Percentage, Vars, Vec<String>)> = None;
+    let max_core = opt.max_core;
+    let topology = Topology::read_from(&opt.sysfs_cpu_path)?;
+    let cluster = match &opt.cluster {
+        Some(name) => Some(topology.cluster(name)?.clone()),
+        None => None,
+    };
+    let category_thresholds = [
+        ("iowait", opt.iowait_threshold),
+        ("steal", opt.steal_threshold),
+    ];
 
-    Loop::new(
-        || {
-            let current = Snapshot::parse(
-                str::from_utf8(fs::read("/proc/stat")?.as_slice())?
-                    .split("\n")
-                    .filter(|line| line.starts_with("cpu "))
-                    .nth(0)
-                    .unwrap(),
+    opt.common
+        .build(|event| {
+            // Refreshes report the usage since the last update, but don't move
+            // the baseline or add to the history, so both still follow the
+            // interval.
+            let refresh = event == Event::Refresh;
+            let gap = if refresh {
+                gaps.pending()
+            } else {
+                gaps.sample()
+            };
+            let mut current = Snapshot::parse(
+                &fs::read_to_string("/proc/stat").context("cannot read /proc/stat")?,
             )?;
+            if let Some(cgroup) = &cgroup {
+                current.cgroup = Some(CgroupUsage::read(cgroup)?);
+            }
+            let processes = if top > 0 {
+                Some(Processes::read()?)
+            } else {
+                None
+            };
+
+            // If the system was suspended, or the counters went backwards, the
+            // deltas from the last snapshot would be nonsense, so we'll start
+            // again from this one.
+            let reset = matches!(&last, Some(last) if gap || !current.follows(last));
+            if reset && !refresh {
+                if mode == Mode::Usage {
+                    history.push_gap();
+                }
+                last = None;
+                last_processes = None;
+            }
+
+            let measured = match mode {
+                Mode::Usage => last.as_ref().filter(|_| !reset).map(|last| {
+                    let usage = current.usage(last, &topology);
+                    let perc = match (usage.cgroup, &cluster) {
+                        (Some(perc), _) => perc,
+                        (None, Some(cluster)) if max_core => usage.max_core(Some(cluster)),
+                        (None, Some(cluster)) => usage.cluster(cluster),
+                        (None, None) if max_core => usage.max_core(None),
+                        (None, None) => usage.overall,
+                    };
+
+                    let extra = category_thresholds
+                        .iter()
+                        .filter(|(name, threshold)| {
+                            matches!(threshold, Some(t) if usage.breakdown.get(name).as_u8() >= *t)
+                        })
+                        .map(|(name, _)| name.to_string())
+                        .collect();
+
+                    let mut vars = usage.vars(&style);
+                    if let (Some(cgroup), Some(sample)) = (&cgroup, &current.cgroup) {
+                        vars = vars
+                            .with("cgroup", cgroup.path().display().to_string())
+                            .with("cgroup_cpus", sample.cpus);
+                    }
+
+                    (perc, vars, extra)
+                }),
+                Mode::Load => {
+                    let load = Load::parse(
+                        &fs::read_to_string("/proc/loadavg")
+                            .context("cannot read /proc/loadavg")?,
+                        &current,
+                    )?;
+                    Some((load.percentage(), load.vars(), Vec::new()))
+                }
+            };
+
+            if let Some((perc, vars, extra)) = measured {
+                if !refresh {
+                    history.push(perc);
+                }
+
+                let top_processes = match (&processes, &last_processes, &last) {
+                    (Some(processes), Some(last_processes), Some(last)) => processes.top(
+                        last_processes,
+                        current.overall.since(&last.overall).total(),
+                        current.cores.len(),
+                        top,
+                    ),
+                    _ => Vec::new(),
+                };
+
+                shown = Some((
+                    perc,
+                    vars.with("top", processes::table(&top_processes)),
+                    extra,
+                ));
+            }
+
+            // After a reset, there's nothing new to show until the next
+            // update, so we'll carry on showing the last values, but with the
+            // gap in the sparkline.
             let mut output = Output {
-                text: String::new(),
-                tooltip: String::new(),
                 class: classes.clone(),
-                percentage: 0,
+                ..Output::default()
             };
+            if let Some((perc, vars, extra)) = &shown {
+                let vars = vars
+                    .clone()
+                    .with("percentage", *perc)
+                    .with("sparkline", history.render(&style, |p| p.as_u8()));
 
-            if let Some(ref last) = last {
-                let perc = Percentage::calculate(
-                    (current.used() - last.used()) as f64,
-                    (current.total() - last.total()) as f64,
-                );
-                history.push(perc);
-
-                output.percentage = perc.as_u8().into();
-                output.tooltip = format!("{}", perc);
-                output.text = history.to_string(|p| p.as_u8());
+                output.percentage = Some(perc.as_u8().into());
+                output.tooltip = tooltip_format.render_optional(&vars);
+                output.text = format.render(&vars);
+                thresholds.apply(*perc, &mut output);
+                output.class.extend(extra.iter().cloned());
             }
 
-            last = Some(current);
+            if !refresh {
+                last = Some(current);
+                last_processes = processes;
+            }
             Ok(output)
-        },
-        &opt.interval.into(),
-    )
-    .run()?;
+        })?
+        .fatal_if(Recovery::not_found)
+        .run()?;
 
     Ok(())
 }
 
+/// Snapshot is a single read of /proc/stat: the aggregate times across all
+/// CPUs, the times for each individual core, and the run queue counters. If
+/// a cgroup is being monitored, it also includes the cgroup's usage.
 #[derive(Debug)]
 struct Snapshot {
-    states: Vec<u64>,
+    overall: Times,
+    cores: Vec<Core>,
+    procs_running: u64,
+    procs_blocked: u64,
+    cgroup: Option<CgroupUsage>,
+}
+
+/// CgroupUsage is the CPU time used by a cgroup so far, along with the number
+/// of CPUs it's allowed to use.
+#[derive(Debug)]
+struct CgroupUsage {
+    usec: u64,
+    cpus: f64,
+    at: Instant,
+}
+
+impl CgroupUsage {
+    fn read(cgroup: &Cgroup) -> Result<Self> {
+        Ok(Self {
+            usec: cgroup.cpu_usage()?,
+            cpus: cgroup.cpus()?,
+            at: Instant::now(),
+        })
+    }
+
+    /// Calculates the share of the cgroup's CPUs that it used since the last
+    /// sample.
+    fn usage(&self, last: &CgroupUsage) -> Percentage {
+        let available = self.at.duration_since(last.at).as_micros() as f64 * self.cpus;
+        if available <= 0. {
+            return Percentage::default();
+        }
+
+        Percentage::from((self.usec.saturating_sub(last.usec) as f64 / available).min(1.))
+    }
+}
+
+#[derive(Debug)]
+struct Core {
+    id: usize,
+    times: Times,
 }
 
 impl Snapshot {
-    fn parse(raw: &str) -> Result<Self> {
+    fn parse(stat: &str) -> Result<Self> {
+        let mut overall = None;
+        let mut cores = Vec::new();
+        let mut procs_running = 0;
+        let mut procs_blocked = 0;
+
+        for line in stat.lines() {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("cpu") => overall = Some(Times::parse(fields)?),
+                Some(name) if name.starts_with("cpu") => cores.push(Core {
+                    id: name["cpu".len()..].parse()?,
+                    times: Times::parse(fields)?,
+                }),
+                Some("procs_running") => procs_running = counter(fields)?,
+                Some("procs_blocked") => procs_blocked = counter(fields)?,
+                _ => {}
+            }
+        }
+
         Ok(Self {
-            states: raw
-                .split_whitespace()
-                .skip(1)
-                .map(|field| Ok(field.parse::<u64>()?))
-                .collect::<Result<Vec<u64>>>()?,
+            overall: overall.ok_or_else(|| anyhow!("no aggregate cpu line in /proc/stat"))?,
+            cores,
+            procs_running,
+            procs_blocked,
+            cgroup: None,
         })
     }
 
+    /// Returns true if none of the aggregate counters have gone backwards
+    /// since the last snapshot.
+    fn follows(&self, last: &Snapshot) -> bool {
+        self.overall.follows(&last.overall)
+            && match (&self.cgroup, &last.cgroup) {
+                (Some(current), Some(last)) => current.usec >= last.usec,
+                _ => true,
+            }
+    }
+
+    /// Calculates the usage since the last snapshot. Cores that weren't
+    /// present in the last snapshot (because they've just been brought
+    /// online), or whose counters have gone backwards, are omitted.
+    fn usage(&self, last: &Snapshot, topology: &Topology) -> Usage {
+        let deltas: Vec<(usize, Times)> = self
+            .cores
+            .iter()
+            .filter_map(|core| {
+                last.cores
+                    .iter()
+                    .find(|prev| prev.id == core.id && core.times.follows(&prev.times))
+                    .map(|prev| (core.id, core.times.since(&prev.times)))
+            })
+            .collect();
+
+        Usage {
+            overall: self.overall.usage(&last.overall),
+            breakdown: self.overall.breakdown(&last.overall),
+            cgroup: match (&self.cgroup, &last.cgroup) {
+                (Some(current), Some(last)) => Some(current.usage(last)),
+                _ => None,
+            },
+            cores: deltas
+                .iter()
+                .map(|(id, delta)| (*id, delta.percentage()))
+                .collect(),
+            // A cluster's usage is the total time its cores were used, rather
+            // than the average of the core percentages, so that cores that
+            // were offline for some of the interval don't skew it.
+            clusters: topology
+                .clusters()
+                .iter()
+                .map(|cluster| {
+                    let (used, total) = deltas
+                        .iter()
+                        .filter(|(id, _)| cluster.contains(*id))
+                        .fold((0, 0), |(used, total), (_, delta)| {
+                            (used + delta.used(), total + delta.total())
+                        });
+
+                    (
+                        cluster.clone(),
+                        Percentage::calculate(used as f64, total as f64),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+fn counter(mut fields: SplitWhitespace) -> Result<u64> {
+    Ok(fields
+        .next()
+        .ok_or_else(|| anyhow!("missing value in /proc/stat"))?
+        .parse()?)
+}
+
+/// Times are the cumulative times from a single cpu line in /proc/stat, in
+/// USER_HZ. Older kernels don't have all of the fields, in which case the
+/// missing ones are zero.
+///
+/// Note that the guest times are also included in the user and nice times, so
+/// they mustn't be counted twice.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Times {
+    user: u64,
+    nice: u64,
+    system: u64,
+    idle: u64,
+    iowait: u64,
+    irq: u64,
+    softirq: u64,
+    steal: u64,
+    guest: u64,
+    guest_nice: u64,
+}
+
+impl Times {
+    fn parse(fields: SplitWhitespace) -> Result<Self> {
+        let values = fields
+            .map(|field| Ok(field.parse::<u64>()?))
+            .collect::<Result<Vec<u64>>>()?;
+        if values.len() < 4 {
+            return Err(anyhow!(
+                "expected at least 4 fields in a cpu line, got {}",
+                values.len()
+            ));
+        }
+
+        let field = |i: usize| values.get(i).copied().unwrap_or_default();
+        Ok(Self {
+            user: field(0),
+            nice: field(1),
+            system: field(2),
+            idle: field(3),
+            iowait: field(4),
+            irq: field(5),
+            softirq: field(6),
+            steal: field(7),
+            guest: field(8),
+            guest_nice: field(9),
+        })
+    }
+
+    /// Returns true if none of the counters have gone backwards since the
+    /// last snapshot.
+    fn follows(&self, last: &Times) -> bool {
+        let ok = |now: u64, then: u64| now >= then;
+
+        ok(self.user, last.user)
+            && ok(self.nice, last.nice)
+            && ok(self.system, last.system)
+            && ok(self.idle, last.idle)
+            && ok(self.iowait, last.iowait)
+            && ok(self.irq, last.irq)
+            && ok(self.softirq, last.softirq)
+            && ok(self.steal, last.steal)
+            && ok(self.guest, last.guest)
+            && ok(self.guest_nice, last.guest_nice)
+    }
+
+    /// Returns the times elapsed between the last snapshot and this one.
+    fn since(&self, last: &Times) -> Times {
+        Times {
+            user: self.user.saturating_sub(last.user),
+            nice: self.nice.saturating_sub(last.nice),
+            system: self.system.saturating_sub(last.system),
+            idle: self.idle.saturating_sub(last.idle),
+            iowait: self.iowait.saturating_sub(last.iowait),
+            irq: self.irq.saturating_sub(last.irq),
+            softirq: self.softirq.saturating_sub(last.softirq),
+            steal: self.steal.saturating_sub(last.steal),
+            guest: self.guest.saturating_sub(last.guest),
+            guest_nice: self.guest_nice.saturating_sub(last.guest_nice),
+        }
+    }
+
+    fn usage(&self, last: &Times) -> Percentage {
+        self.since(last).percentage()
+    }
+
+    /// Returns the share of the total time that was used, for times that are
+    /// already deltas.
+    fn percentage(&self) -> Percentage {
+        Percentage::calculate(self.used() as f64, self.total() as f64)
+    }
+
+    fn breakdown(&self, last: &Times) -> Breakdown {
+        let delta = self.since(last);
+        let total = delta.total() as f64;
+        let perc = |n: u64| Percentage::calculate(n as f64, total);
+
+        Breakdown {
+            categories: vec![
+                ("user", perc(delta.user.saturating_sub(delta.guest))),
+                ("nice", perc(delta.nice.saturating_sub(delta.guest_nice))),
+                ("system", perc(delta.system)),
+                ("irq", perc(delta.irq + delta.softirq)),
+                ("iowait", perc(delta.iowait)),
+                ("steal", perc(delta.steal)),
+                ("guest", perc(delta.guest + delta.guest_nice)),
+                ("idle", perc(delta.idle)),
+            ],
+        }
+    }
+
+    /// Time the CPU wasn't doing anything, including time spent waiting for
+    /// I/O.
     fn idle(&self) -> u64 {
-        *self.states.get(3).unwrap()
+        self.idle + self.iowait
     }
 
     fn used(&self) -> u64 {
@@ -94,6 +560,410 @@ impl Snapshot {
     }
 
     fn total(&self) -> u64 {
-        self.states.iter().fold(0, |acc, n| acc + *n)
+        self.user
+            + self.nice
+            + self.system
+            + self.idle
+            + self.iowait
+            + self.irq
+            + self.softirq
+            + self.steal
+    }
+}
+
+/// Breakdown is the percentage of time spent in each category.
+#[derive(Debug)]
+struct Breakdown {
+    categories: Vec<(&'static str, Percentage)>,
+}
+
+impl Breakdown {
+    fn get(&self, name: &str) -> Percentage {
+        self.categories
+            .iter()
+            .find(|(category, _)| *category == name)
+            .map(|(_, perc)| *perc)
+            .unwrap_or_default()
+    }
+
+    /// Renders a table with a line for each category.
+    fn table(&self) -> String {
+        let width = self
+            .categories
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or_default();
+
+        self.categories
+            .iter()
+            .map(|(name, perc)| format!("{:<width$} {:>3}%", name, perc.as_u8(), width = width))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[derive(Debug)]
+struct Usage {
+    overall: Percentage,
+    breakdown: Breakdown,
+    cgroup: Option<Percentage>,
+    cores: Vec<(usize, Percentage)>,
+    clusters: Vec<(Cluster, Percentage)>,
+}
+
+impl Usage {
+    fn vars(&self, style: &Style) -> Vars {
+        let mut vars = Vars::new()
+            .with("overall", self.overall)
+            .with("max_core", self.max_core(None))
+            .with("core_sparkline", self.sparkline(style))
+            .with("core_table", self.table())
+            .with("cluster_table", self.cluster_table())
+            .with("breakdown", self.breakdown.table());
+        for (name, perc) in self.breakdown.categories.iter() {
+            vars = vars.with(name, *perc);
+        }
+        for (cluster, perc) in self.clusters.iter() {
+            vars = vars.with(&format!("cluster_{}", cluster.name), *perc);
+        }
+
+        vars
+    }
+
+    /// Returns the usage of the busiest core, optionally only considering the
+    /// cores in a cluster.
+    fn max_core(&self, cluster: Option<&Cluster>) -> Percentage {
+        self.cores
+            .iter()
+            .filter(|(id, _)| match cluster {
+                Some(cluster) => cluster.contains(*id),
+                None => true,
+            })
+            .map(|(_, perc)| *perc)
+            .max()
+            .unwrap_or(self.overall)
+    }
+
+    fn cluster(&self, cluster: &Cluster) -> Percentage {
+        self.clusters
+            .iter()
+            .find(|(c, _)| c.name == cluster.name)
+            .map(|(_, perc)| *perc)
+            .unwrap_or_default()
+    }
+
+    /// Renders the current usage of each core as one value in a sparkline.
+    fn sparkline<R>(&self, renderer: &R) -> String
+    where
+        R: Renderer,
+    {
+        renderer.render(
+            &self
+                .cores
+                .iter()
+                .map(|(_, perc)| perc.as_u8())
+                .collect::<Vec<u8>>(),
+        )
+    }
+
+    /// Renders a table with a line for each core. On a hybrid CPU, the cores
+    /// are grouped by cluster, with each group headed by the cluster's usage.
+    fn table(&self) -> String {
+        let width = self
+            .cores
+            .iter()
+            .map(|(id, _)| id.to_string().len())
+            .max()
+            .unwrap_or_default();
+        let line = |(id, perc): &(usize, Percentage)| {
+            format!("CPU{:<width$} {:>3}%", id, perc.as_u8(), width = width)
+        };
+
+        if self.clusters.len() < 2 {
+            return self
+                .cores
+                .iter()
+                .map(line)
+                .collect::<Vec<String>>()
+                .join("\n");
+        }
+
+        self.clusters
+            .iter()
+            .map(|(cluster, perc)| {
+                let mut lines = vec![format!("{} {}%", cluster.label, perc.as_u8())];
+                lines.extend(
+                    self.cores
+                        .iter()
+                        .filter(|(id, _)| cluster.contains(*id))
+                        .map(line),
+                );
+
+                lines.join("\n")
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n")
+    }
+
+    /// Renders a table with a line for each cluster.
+    fn cluster_table(&self) -> String {
+        let width = self
+            .clusters
+            .iter()
+            .map(|(cluster, _)| cluster.label.len())
+            .max()
+            .unwrap_or_default();
+
+        self.clusters
+            .iter()
+            .map(|(cluster, perc)| {
+                format!(
+                    "{:<width$} {:>3}%",
+                    cluster.label,
+                    perc.as_u8(),
+                    width = width
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// Load is the load average from /proc/loadavg, along with the run queue
+/// counters and the number of online cores from /proc/stat.
+#[derive(Debug)]
+struct Load {
+    one: f64,
+    five: f64,
+    fifteen: f64,
+    running: u64,
+    blocked: u64,
+    cores: usize,
+}
+
+impl Load {
+    fn parse(loadavg: &str, snapshot: &Snapshot) -> Result<Self> {
+        let averages = loadavg
+            .split_whitespace()
+            .take(3)
+            .map(|field| Ok(field.parse::<f64>()?))
+            .collect::<Result<Vec<f64>>>()?;
+        if averages.len() < 3 {
+            return Err(anyhow!("malformed /proc/loadavg: {:?}", loadavg));
+        }
+
+        Ok(Self {
+            one: averages[0],
+            five: averages[1],
+            fifteen: averages[2],
+            running: snapshot.procs_running,
+            blocked: snapshot.procs_blocked,
+            cores: snapshot.cores.len(),
+        })
+    }
+
+    /// Returns the one minute load average relative to the number of cores,
+    /// capped at 100%.
+    fn percentage(&self) -> Percentage {
+        if self.cores == 0 {
+            return Percentage::default();
+        }
+
+        Percentage::from((self.one / self.cores as f64).min(1.))
+    }
+
+    fn vars(&self) -> Vars {
+        Vars::new()
+            .with("load1", self.one)
+            .with("load5", self.five)
+            .with("load15", self.fifteen)
+            .with("running", self.running)
+            .with("blocked", self.blocked)
+            .with("cores", self.cores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use waybar::Blocks;
+
+    fn uniform() -> Topology {
+        Topology::new(vec![Cluster {
+            name: "all".into(),
+            label: "All cores".into(),
+            cpus: vec![0, 1],
+        }])
+    }
+
+    const STAT: &str = "cpu  100 0 100 800 0 0 0 0 0 0
+cpu0 50 0 50 400 0 0 0 0 0 0
+cpu1 50 0 50 400 0 0 0 0 0 0
+intr 12345
+ctxt 67890
+procs_running 3
+procs_blocked 1
+";
+
+    const LATER: &str = "cpu  300 0 100 1400 0 0 0 0 0 0
+cpu0 250 0 50 400 0 0 0 0 0 0
+cpu1 50 0 50 1000 0 0 0 0 0 0
+intr 12345
+ctxt 67890
+";
+
+    #[test]
+    fn test_parse() {
+        let snapshot = Snapshot::parse(STAT).unwrap();
+
+        assert_eq!(1000, snapshot.overall.total());
+        assert_eq!(200, snapshot.overall.used());
+        assert_eq!(
+            vec![0, 1],
+            snapshot
+                .cores
+                .iter()
+                .map(|core| core.id)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(3, snapshot.procs_running);
+        assert_eq!(1, snapshot.procs_blocked);
+        assert!(Snapshot::parse("intr 12345").is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let snapshot = Snapshot::parse(STAT).unwrap();
+        let load = Load::parse("0.50 1.00 4.00 2/345 6789\n", &snapshot).unwrap();
+
+        assert_eq!(25, load.percentage().as_u8());
+        assert_eq!(
+            "Load: 0.50, 1.00, 4.00\n3 running, 1 blocked",
+            LOAD_TOOLTIP
+                .parse::<Template>()
+                .unwrap()
+                .render(&load.vars())
+        );
+
+        let load = Load::parse("4.00 1.00 0.50 2/345 6789\n", &snapshot).unwrap();
+        assert_eq!(100, load.percentage().as_u8());
+        assert!(Load::parse("0.50", &snapshot).is_err());
+    }
+
+    #[test]
+    fn test_usage() {
+        let usage = Snapshot::parse(LATER)
+            .unwrap()
+            .usage(&Snapshot::parse(STAT).unwrap(), &uniform());
+
+        assert_eq!(25, usage.overall.as_u8());
+        assert_eq!(100, usage.max_core(None).as_u8());
+        assert_eq!("█▁", usage.sparkline(&Blocks));
+        assert_eq!("CPU0 100%\nCPU1   0%", usage.table());
+    }
+
+    #[test]
+    fn test_clusters() {
+        let cluster = |name: &str, label: &str, cpu: usize| Cluster {
+            name: name.into(),
+            label: label.into(),
+            cpus: vec![cpu],
+        };
+        let topology = Topology::new(vec![cluster("p", "P-cores", 0), cluster("e", "E-cores", 1)]);
+        let usage = Snapshot::parse(LATER)
+            .unwrap()
+            .usage(&Snapshot::parse(STAT).unwrap(), &topology);
+
+        let e = topology.cluster("e").unwrap();
+        assert_eq!(100, usage.cluster(topology.cluster("p").unwrap()).as_u8());
+        assert_eq!(0, usage.cluster(e).as_u8());
+        assert_eq!(0, usage.max_core(Some(e)).as_u8());
+        assert_eq!("P-cores 100%\nE-cores   0%", usage.cluster_table());
+        assert_eq!(
+            "P-cores 100%\nCPU0 100%\n\nE-cores 0%\nCPU1   0%",
+            usage.table()
+        );
+        assert_eq!(
+            "100",
+            "{cluster_p}"
+                .parse::<Template>()
+                .unwrap()
+                .render(&usage.vars(&Style::Blocks))
+        );
+    }
+
+    #[test]
+    fn test_non_monotonic() {
+        let last = Snapshot::parse(LATER).unwrap();
+        let current = Snapshot::parse(STAT).unwrap();
+        assert!(!current.follows(&last));
+        assert!(current.follows(&current));
+
+        // A core whose counters reset (say, because it was taken offline and
+        // brought back) is left out, rather than reported as some huge value.
+        let current = Snapshot::parse(
+            "cpu  400 0 100 2000 0 0 0 0 0 0
+cpu0 350 0 50 1000 0 0 0 0 0 0
+cpu1 10 0 10 10 0 0 0 0 0 0
+",
+        )
+        .unwrap();
+        let usage = current.usage(&last, &uniform());
+        assert!(current.follows(&last));
+        assert_eq!(
+            vec![0],
+            usage.cores.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_cgroup_usage() {
+        let now = Instant::now();
+        let last = CgroupUsage {
+            usec: 1_000_000,
+            cpus: 0.5,
+            at: now,
+        };
+        let usage = |usec: u64| {
+            CgroupUsage {
+                usec,
+                cpus: 0.5,
+                at: now + std::time::Duration::from_secs(2),
+            }
+            .usage(&last)
+            .as_u8()
+        };
+
+        // Half a second of CPU time over two seconds, with a quota of half a
+        // CPU, is half of the quota.
+        assert_eq!(50, usage(1_500_000));
+        assert_eq!(100, usage(3_000_000));
+        assert_eq!(0, usage(500_000));
+    }
+
+    #[test]
+    fn test_accounting() {
+        // 200 ticks of user time (50 of which were guest time), 100 of iowait,
+        // 50 of steal, and 650 idle.
+        let last = Snapshot::parse("cpu  0 0 0 0 0 0 0 0 0 0").unwrap();
+        let current = Snapshot::parse("cpu  200 0 0 650 100 0 0 50 50 0").unwrap();
+        let usage = current.usage(&last, &uniform());
+
+        assert_eq!(25, usage.overall.as_u8());
+        assert_eq!(15, usage.breakdown.get("user").as_u8());
+        assert_eq!(5, usage.breakdown.get("guest").as_u8());
+        assert_eq!(10, usage.breakdown.get("iowait").as_u8());
+        assert_eq!(5, usage.breakdown.get("steal").as_u8());
+        assert_eq!(65, usage.breakdown.get("idle").as_u8());
+        assert!(usage
+            .breakdown
+            .table()
+            .starts_with("user    15%\nnice     0%\n"));
+
+        // Old kernels only have the first four fields.
+        let old = Snapshot::parse("cpu  10 20 30 40").unwrap();
+        assert_eq!(100, old.overall.total());
+        assert!(Snapshot::parse("cpu  10 20 30").is_err());
     }
 }
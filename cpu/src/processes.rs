@@ -0,0 +1,205 @@
+use anyhow::{anyhow, Error, Result};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+
+/// Processes is a snapshot of the CPU time used by each process so far, from
+/// /proc/[pid]/stat.
+#[derive(Debug, Default)]
+pub(crate) struct Processes(HashMap<u32, Process>);
+
+#[derive(Debug, Clone, PartialEq)]
+struct Process {
+    name: String,
+    start: u64,
+    ticks: u64,
+}
+
+/// Top is a process that used CPU time between two snapshots, with its share
+/// of the CPU time that elapsed as a percentage of a single core, in the same
+/// way as top: a process keeping two cores busy is at 200%.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Top {
+    pub(crate) pid: u32,
+    pub(crate) name: String,
+    pub(crate) share: f64,
+}
+
+impl Processes {
+    pub(crate) fn read() -> Result<Self> {
+        let mut processes = HashMap::new();
+
+        for entry in fs::read_dir("/proc")? {
+            let entry = entry?;
+            let pid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+                Some(pid) => pid,
+                None => continue,
+            };
+
+            // Processes can exit between listing /proc and reading their stat
+            // file, so we'll just skip anything that can't be read.
+            if let Ok(process) = fs::read_to_string(entry.path().join("stat"))
+                .map_err(Error::from)
+                .and_then(|stat| Process::parse(&stat))
+            {
+                processes.insert(pid, process);
+            }
+        }
+
+        Ok(Self(processes))
+    }
+
+    /// Returns up to n processes that used the most CPU time since the last
+    /// snapshot, given the total CPU time (across all cores) that elapsed and
+    /// the number of cores.
+    pub(crate) fn top(&self, last: &Processes, total: u64, cores: usize, n: usize) -> Vec<Top> {
+        if total == 0 {
+            return Vec::new();
+        }
+        let per_core = total as f64 / cores.max(1) as f64;
+
+        let mut top: Vec<Top> = self
+            .0
+            .iter()
+            .filter_map(|(pid, process)| {
+                // If the process wasn't in the last snapshot, or the PID has
+                // since been reused, then all of its time is new. (Kernel
+                // threads rename themselves, so the start time is the only
+                // reliable way to detect reuse.)
+                let ticks = match last.0.get(pid) {
+                    Some(prev) if prev.start == process.start && prev.ticks <= process.ticks => {
+                        process.ticks - prev.ticks
+                    }
+                    _ => process.ticks,
+                };
+
+                if ticks == 0 {
+                    None
+                } else {
+                    Some(Top {
+                        pid: *pid,
+                        name: process.name.clone(),
+                        share: 100. * ticks as f64 / per_core,
+                    })
+                }
+            })
+            .collect();
+
+        top.sort_by(|a, b| {
+            b.share
+                .partial_cmp(&a.share)
+                .unwrap_or(Ordering::Equal)
+                .then(a.pid.cmp(&b.pid))
+        });
+        top.truncate(n);
+        top
+    }
+}
+
+impl Process {
+    fn parse(stat: &str) -> Result<Self> {
+        // The command name is in parentheses, and can itself contain spaces
+        // and parentheses, so we need to look for the last closing parenthesis
+        // to find the end of it.
+        let (start, end) = match (stat.find('('), stat.rfind(')')) {
+            (Some(start), Some(end)) if start < end => (start, end),
+            _ => return Err(anyhow!("malformed process stat: {:?}", stat)),
+        };
+
+        // The fields after the name start at field 3 (state), so utime and
+        // stime (fields 14 and 15) are at indices 11 and 12, and starttime
+        // (field 22) is at index 19.
+        let fields: Vec<&str> = stat[end + 1..].split_whitespace().collect();
+        let field = |i: usize| -> Result<u64> {
+            Ok(fields
+                .get(i)
+                .ok_or_else(|| anyhow!("missing field {} in process stat", i + 3))?
+                .parse()?)
+        };
+
+        Ok(Self {
+            name: stat[start + 1..end].into(),
+            start: field(19)?,
+            ticks: field(11)? + field(12)?,
+        })
+    }
+}
+
+/// Renders a table with a line for each process.
+pub(crate) fn table(top: &[Top]) -> String {
+    top.iter()
+        .map(|process| format!("{:>5.1}% {} ({})", process.share, process.name, process.pid))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn processes(entries: &[(u32, &str, u64, u64)]) -> Processes {
+        Processes(
+            entries
+                .iter()
+                .map(|(pid, name, start, ticks)| {
+                    (
+                        *pid,
+                        Process {
+                            name: name.to_string(),
+                            start: *start,
+                            ticks: *ticks,
+                        },
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Process {
+                name: "Web Content (x)".into(),
+                start: 9876,
+                ticks: 350,
+            },
+            Process::parse(
+                "1234 (Web Content (x)) S 1 1234 1234 0 -1 4194560 100 0 0 0 300 50 0 0 20 0 1 0 9876 0"
+            )
+            .unwrap()
+        );
+        assert!(Process::parse("1234 (short) S 1").is_err());
+        assert!(Process::parse("garbage").is_err());
+    }
+
+    #[test]
+    fn test_top() {
+        let last = processes(&[
+            (1, "init", 1, 100),
+            (2, "firefox", 10, 1000),
+            (3, "old", 20, 500),
+        ]);
+        let current = processes(&[
+            (1, "init", 1, 100),
+            (2, "firefox", 10, 1300),
+            (3, "new", 30, 50),
+            (4, "cargo", 40, 100),
+        ]);
+
+        let top = current.top(&last, 1000, 1, 2);
+        assert_eq!(
+            vec![(2, 30.), (4, 10.)],
+            top.iter()
+                .map(|process| (process.pid, process.share))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(" 30.0% firefox (2)\n 10.0% cargo (4)", table(&top));
+
+        // Shares are relative to a single core, like top.
+        assert_eq!(120., current.top(&last, 1000, 4, 1)[0].share);
+
+        // PID 3 was reused, so all of its time counts.
+        assert_eq!(3, current.top(&last, 1000, 1, 10).len());
+        assert!(current.top(&last, 0, 1, 10).is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{anyhow, Result};
+use structopt::StructOpt;
+
+use crate::cpu::{Policy, Setting};
+
+/// Command changes a setting on every policy, typically from a waybar `on-click`
+/// or `on-scroll` handler.
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Changes the scaling governor
+    Governor(Action),
+
+    /// Changes the energy performance preference
+    Epp(Action),
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Action {
+    /// Switches to the next available value
+    Next,
+
+    /// Switches to the previous available value
+    Prev,
+
+    /// Switches to the given value
+    Set { value: String },
+}
+
+impl Command {
+    pub fn run(&self, policies: &[Policy]) -> Result<()> {
+        match self {
+            Command::Governor(action) => apply(policies, Setting::Governor, action),
+            Command::Epp(action) => apply(policies, Setting::Epp, action),
+        }
+    }
+}
+
+fn apply(policies: &[Policy], setting: Setting, action: &Action) -> Result<()> {
+    let policies: Vec<&Policy> = policies
+        .iter()
+        .filter(|policy| policy.is_active() && matches!(policy.setting(setting), Ok(Some(_))))
+        .collect();
+    let first = policies
+        .first()
+        .ok_or_else(|| anyhow!("no cores support changing the {}", setting.name()))?;
+
+    // Policies can disagree, but cycling from the first one's value brings them
+    // all back into line.
+    let current = first.setting(setting)?.unwrap_or_default();
+    let available = first.available(setting)?;
+    let value = target(&available, &current, action).ok_or_else(|| match action {
+        Action::Set { value } => anyhow!(
+            "{} isn't an available {}: expected one of {}",
+            value,
+            setting.name(),
+            available.join(", ")
+        ),
+        _ => anyhow!("no available values for the {}", setting.name()),
+    })?;
+
+    for policy in policies {
+        policy.set(setting, value)?;
+    }
+
+    Ok(())
+}
+
+/// Works out the value to switch to. Cycling wraps around, and starts from the
+/// first value if the current value isn't available.
+fn target<'a>(available: &'a [String], current: &str, action: &Action) -> Option<&'a str> {
+    let pos = available.iter().position(|value| value == current);
+    let index = match (action, pos) {
+        (Action::Set { value }, _) => available.iter().position(|v| v == value)?,
+        (_, None) => 0,
+        (Action::Next, Some(pos)) => (pos + 1) % available.len(),
+        (Action::Prev, Some(pos)) => (pos + available.len() - 1) % available.len(),
+    };
+
+    available.get(index).map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Files;
+    use crate::testing;
+    use waybar::testing::Tree;
+
+    #[test]
+    fn test_target() {
+        let available: Vec<String> = vec!["performance".into(), "powersave".into()];
+
+        assert_eq!(
+            Some("powersave"),
+            target(&available, "performance", &Action::Next)
+        );
+        assert_eq!(
+            Some("performance"),
+            target(&available, "powersave", &Action::Next)
+        );
+        assert_eq!(
+            Some("powersave"),
+            target(&available, "performance", &Action::Prev)
+        );
+        assert_eq!(
+            Some("performance"),
+            target(&available, "schedutil", &Action::Prev)
+        );
+        assert_eq!(
+            None,
+            target(
+                &available,
+                "performance",
+                &Action::Set {
+                    value: "ondemand".into()
+                }
+            )
+        );
+        assert_eq!(None, target(&[], "performance", &Action::Next));
+    }
+
+    #[test]
+    fn test_apply() {
+        let root = Tree::new("cpufreq-control");
+        for cpu in 0..2 {
+            let cpus = format!("{}\n", cpu);
+            testing::policy(&root, cpu, &cpus, &cpus);
+            root.write(
+                format!("cpufreq/policy{}/scaling_governor", cpu),
+                "powersave\n",
+            );
+            root.write(
+                format!("cpufreq/policy{}/scaling_available_governors", cpu),
+                "performance powersave\n",
+            );
+        }
+
+        let policies = Policy::discover(root.path(), Files::Scaling).unwrap();
+        assert_eq!(2, policies.len());
+        Command::Governor(Action::Next).run(&policies).unwrap();
+        for policy in policies.iter() {
+            assert_eq!(
+                Some("performance".to_string()),
+                policy.setting(Setting::Governor).unwrap()
+            );
+        }
+        assert!(Command::Epp(Action::Next).run(&policies).is_err());
+    }
+}
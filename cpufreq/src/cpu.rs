@@ -1,5 +1,5 @@
 use std::num::ParseIntError;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::{self, Utf8Error};
 use std::{fs, io};
 use thiserror::Error;
@@ -17,44 +17,135 @@ pub enum Error {
 
     #[error("cannot find scaling frequency file for CPU: {0}")]
     MissingScalingFile(String),
+
+    #[error("malformed CPU list in {0}")]
+    MalformedCpuList(String),
+
+    #[error("cannot set {0} to {1} in {2} (are you root?)")]
+    SetDenied(&'static str, String, String, #[source] io::Error),
+
+    #[error("cannot set {0} to {1} in {2}")]
+    Set(&'static str, String, String, #[source] io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// Returns true if the error is because the policy's CPUs were taken
+    /// offline while we were reading it: the kernel refuses to read inactive
+    /// policies with EBUSY.
+    pub fn is_offline(&self) -> bool {
+        matches!(self, Error::Discover(e) if e.kind() == io::ErrorKind::ResourceBusy)
+    }
+}
+
+/// Files selects which of a policy's files the frequencies are read from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Files {
+    /// The frequency the driver last set, and the limits the governor works
+    /// within. Most drivers provide these.
+    Scaling,
+
+    /// The frequency the hardware reports it's actually running at, and the
+    /// hardware limits. These are usually only readable by root.
+    Cpuinfo,
+}
+
+impl Files {
+    pub fn current(&self) -> &'static str {
+        match self {
+            Files::Scaling => "scaling_cur_freq",
+            Files::Cpuinfo => "cpuinfo_cur_freq",
+        }
+    }
+
+    fn max(&self) -> &'static str {
+        match self {
+            Files::Scaling => "scaling_max_freq",
+            Files::Cpuinfo => "cpuinfo_max_freq",
+        }
+    }
+
+    fn min(&self) -> &'static str {
+        match self {
+            Files::Scaling => "scaling_min_freq",
+            Files::Cpuinfo => "cpuinfo_min_freq",
+        }
+    }
+}
+
+/// Policy is a cpufreq policy: a group of CPUs that always run at the same
+/// frequency, such as every core in an ARM cluster, or (on most x86 systems)
+/// a single core. Reading the policy once covers all of its CPUs.
 #[derive(Debug)]
-pub struct CPU {
+pub struct Policy {
+    id: usize,
+    cpus: Vec<usize>,
+    related: Vec<usize>,
+    root: PathBuf,
     current: PathBuf,
     max: PathBuf,
     min: PathBuf,
 }
 
-impl CPU {
-    pub fn discover(path: &PathBuf) -> Result<Vec<Self>> {
-        path.read_dir()?
-            .filter_map(|e| e.ok())
-            .map(|e| e.path())
-            .filter(|path| path.is_dir())
-            .filter(|path| {
-                let mut freq = path.clone();
-                freq.push("cpufreq");
-                freq.is_dir()
-            })
-            .map(|path| Ok(Self::new(&path)?))
-            .collect()
-    }
+impl Policy {
+    /// Finds the policies under the CPU sysfs path, which will read their
+    /// frequencies from the given files. Policies only change as CPUs are
+    /// brought online and taken offline, so this is cheap enough to repeat on
+    /// every update.
+    pub fn discover(path: &Path, files: Files) -> Result<Vec<Self>> {
+        let dir = match path.join("cpufreq").read_dir() {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
 
-    fn new(path: &PathBuf) -> Result<Self> {
-        let mut root = path.clone();
+        let mut policies = Vec::new();
+        for path in dir.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if let Some(id) = policy_id(&path) {
+                policies.push(Self::new(id, &path, files)?);
+            }
+        }
 
-        root.push("cpufreq");
+        policies.sort_by_key(|policy| policy.id);
+        Ok(policies)
+    }
 
+    fn new(id: usize, root: &Path, files: Files) -> Result<Self> {
         Ok(Self {
-            current: path_buf_file(&root, "scaling_cur_freq")?,
-            max: path_buf_file(&root, "scaling_max_freq")?,
-            min: path_buf_file(&root, "scaling_min_freq")?,
+            id,
+            cpus: cpu_list(&root.join("affected_cpus"))?,
+            related: cpu_list(&root.join("related_cpus"))?,
+            root: root.to_path_buf(),
+            current: path_buf_file(root, files.current())?,
+            max: path_buf_file(root, files.max())?,
+            min: path_buf_file(root, files.min())?,
         })
     }
 
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns the online CPUs in the policy.
+    pub fn cpus(&self) -> &[usize] {
+        &self.cpus
+    }
+
+    /// Returns true if any of the policy's CPUs are online. The kernel keeps
+    /// the policies of offline CPUs around, but refuses to read from them.
+    pub fn is_active(&self) -> bool {
+        !self.cpus.is_empty()
+    }
+
+    /// Returns the number of CPUs in the policy that are offline.
+    pub fn offline(&self) -> usize {
+        self.related
+            .iter()
+            .filter(|cpu| !self.cpus.contains(cpu))
+            .count()
+    }
+
     pub fn current_freq(&self) -> Result<Frequency> {
         file_freq(&self.current)
     }
@@ -66,12 +157,118 @@ impl CPU {
     pub fn min_freq(&self) -> Result<Frequency> {
         file_freq(&self.min)
     }
+
+    /// Returns the time the policy has spent at each frequency, in units of
+    /// 10ms, or None if the kernel doesn't keep statistics for it.
+    pub fn time_in_state(&self) -> Result<Option<Vec<(Frequency, u64)>>> {
+        optional_file(&self.root.join("stats/time_in_state"))?
+            .map(|s| {
+                s.lines()
+                    .filter_map(|line| {
+                        let mut fields = line.split_whitespace();
+                        Some((fields.next()?, fields.next()?))
+                    })
+                    .map(|(freq, time)| Ok((freq.parse()?, time.parse()?)))
+                    .collect()
+            })
+            .transpose()
+    }
+
+    /// Returns the number of times the policy has changed frequency, or None
+    /// if the kernel doesn't keep statistics for it.
+    pub fn total_trans(&self) -> Result<Option<u64>> {
+        Ok(optional_file(&self.root.join("stats/total_trans"))?
+            .map(|s| s.trim().parse())
+            .transpose()?)
+    }
+
+    /// Returns the current value of a setting, or None if the driver doesn't
+    /// support it.
+    pub fn setting(&self, setting: Setting) -> Result<Option<String>> {
+        Ok(optional_file(&self.root.join(setting.file()))?.map(|s| s.trim().to_string()))
+    }
+
+    /// Returns the values the setting can be changed to.
+    pub fn available(&self, setting: Setting) -> Result<Vec<String>> {
+        Ok(optional_file(&self.root.join(setting.available_file()))?
+            .map(|s| s.split_whitespace().map(String::from).collect())
+            .unwrap_or_default())
+    }
+
+    pub fn set(&self, setting: Setting, value: &str) -> Result<()> {
+        let path = self.root.join(setting.file());
+
+        // The kernel also rejects values with EINVAL and busy policies with
+        // EBUSY, so root is only worth suggesting for permission errors.
+        fs::write(&path, value).map_err(|e| {
+            let error = match e.kind() {
+                io::ErrorKind::PermissionDenied => Error::SetDenied,
+                _ => Error::Set,
+            };
+            error(
+                setting.name(),
+                value.into(),
+                path.to_string_lossy().into(),
+                e,
+            )
+        })
+    }
 }
 
-fn path_buf_file(root: &PathBuf, file: &str) -> Result<PathBuf> {
-    let mut pb = root.clone();
+/// Setting is one of the cpufreq tunables that can be shown and changed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Setting {
+    Governor,
+    Epp,
+}
 
-    pb.push(file);
+impl Setting {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Setting::Governor => "governor",
+            Setting::Epp => "epp",
+        }
+    }
+
+    fn file(&self) -> &'static str {
+        match self {
+            Setting::Governor => "scaling_governor",
+            Setting::Epp => "energy_performance_preference",
+        }
+    }
+
+    fn available_file(&self) -> &'static str {
+        match self {
+            Setting::Governor => "scaling_available_governors",
+            Setting::Epp => "energy_performance_available_preferences",
+        }
+    }
+}
+
+/// Parses the policy number out of a directory name like `policy12`.
+fn policy_id(path: &Path) -> Option<usize> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("policy")?
+        .parse()
+        .ok()
+}
+
+/// Reads a space separated list of CPUs, such as `affected_cpus`. A missing
+/// file is treated as an empty list.
+fn cpu_list(file: &Path) -> Result<Vec<usize>> {
+    optional_file(file)?
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|cpu| {
+            cpu.parse()
+                .map_err(|_| Error::MalformedCpuList(file.to_string_lossy().into()))
+        })
+        .collect()
+}
+
+fn path_buf_file(root: &Path, file: &str) -> Result<PathBuf> {
+    let pb = root.join(file);
 
     if pb.exists() {
         Ok(pb)
@@ -80,10 +277,75 @@ fn path_buf_file(root: &PathBuf, file: &str) -> Result<PathBuf> {
     }
 }
 
-fn file_freq(file: &PathBuf) -> Result<Frequency> {
+fn optional_file(file: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(file) {
+        Ok(s) => Ok(Some(s)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn file_freq(file: &Path) -> Result<Frequency> {
     Ok(str::from_utf8(fs::read(file)?.as_slice())?
         .trim()
         .parse::<Frequency>()?)
 }
 
 pub type Frequency = u64;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use waybar::testing::Tree;
+
+    #[test]
+    fn test_discover() {
+        let root = Tree::new("cpufreq-discover");
+
+        // Nothing at all is fine: the caller decides what to do about it.
+        assert!(Policy::discover(root.path(), Files::Scaling)
+            .unwrap()
+            .is_empty());
+
+        testing::policy(&root, 4, "4 5\n", "4 5 6 7\n");
+        testing::policy(&root, 0, "0 1 2 3\n", "0 1 2 3\n");
+        testing::policy(&root, 8, "\n", "8 9\n");
+
+        let policies = Policy::discover(root.path(), Files::Scaling).unwrap();
+        assert_eq!(
+            vec![0, 4, 8],
+            policies
+                .iter()
+                .map(|policy| policy.id())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(&[4, 5], policies[1].cpus());
+        assert_eq!(2, policies[1].offline());
+        assert!(policies[1].is_active());
+        assert!(!policies[2].is_active());
+        assert_eq!(2, policies[2].offline());
+
+        assert!(matches!(
+            Policy::discover(root.path(), Files::Cpuinfo),
+            Err(Error::MissingScalingFile(_))
+        ));
+
+        fs::remove_dir_all(root.join("cpufreq/policy8")).unwrap();
+        let err = policies[2].set(Setting::Governor, "powersave").unwrap_err();
+        assert!(matches!(err, Error::Set(..)));
+        assert_eq!(
+            format!(
+                "cannot set governor to powersave in {}",
+                root.join("cpufreq/policy8/scaling_governor").display()
+            ),
+            err.to_string()
+        );
+
+        testing::policy(&root, 12, "x\n", "12\n");
+        assert!(matches!(
+            Policy::discover(root.path(), Files::Scaling),
+            Err(Error::MalformedCpuList(_))
+        ));
+    }
+}
@@ -1,20 +1,37 @@
-use anyhow::Result;
-use cpu::{Frequency, CPU};
-use humantime::Duration;
+use anyhow::{anyhow, Result};
+use cpu::{Files, Frequency, Policy, Setting};
 use std::fmt;
 use std::path::PathBuf;
 use structopt::StructOpt;
-use waybar::{History, Loop, Output, Percentage};
+use waybar::{CommonOpt, Event, History, Output, Percentage, Renderer, Template, Topology, Vars};
+
+mod control;
+use control::Command;
 
 pub mod cpu;
 
+mod source;
+use source::Reading;
+
+mod stats;
+use stats::{Residency, Stats, Window};
+
+mod throttle;
+use throttle::{Counts, Throttled};
+
+mod turbo;
+use turbo::Turbo;
+
+#[cfg(test)]
+mod testing;
+
 #[derive(Debug, StructOpt)]
 struct Opt {
     #[structopt(short, long, default_value = "cpufreq", help = "CSS class")]
     class: String,
 
-    #[structopt(short, long, default_value = "1s", help = "interval between updates")]
-    interval: Duration,
+    #[structopt(flatten)]
+    common: CommonOpt,
 
     #[structopt(
         short,
@@ -25,70 +42,329 @@ struct Opt {
     )]
     sparkline: u8,
 
+    #[structopt(
+        long,
+        help = "show each core's current frequency as one value in a sparkline, rather than the history of the average"
+    )]
+    per_core: bool,
+
+    #[structopt(
+        long,
+        help = "format for the module text [default: {sparkline}, or {core_sparkline} with --per-core]"
+    )]
+    format: Option<Template>,
+
+    #[structopt(
+        long,
+        help = "format for the tooltip [default: depends on what the CPU supports]"
+    )]
+    tooltip_format: Option<Template>,
+
+    #[structopt(
+        long,
+        name = "CLUSTER",
+        help = "report the frequency of a single cluster of cores, such as p or e on a hybrid CPU"
+    )]
+    cluster: Option<String>,
+
     #[structopt(
         long,
         default_value = "/sys/devices/system/cpu",
         help = "base path to the CPU sysfs"
     )]
     sysfs_cpu_path: PathBuf,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
+const SETTINGS: [Setting; 2] = [Setting::Governor, Setting::Epp];
+
 fn main() -> Result<()> {
     let opt = Opt::from_args();
-    let classes = vec![opt.class.into()];
+    let classes = vec![opt.class];
 
-    // Enumerate cores.
-    let cores = CPU::discover(&opt.sysfs_cpu_path)?;
+    if let Some(command) = &opt.command {
+        return command.run(&Policy::discover(&opt.sysfs_cpu_path, Files::Scaling)?);
+    }
+
+    // Work out where to read frequencies from, and which cluster each core
+    // belongs to.
+    let mut source = match source::detect(&opt.sysfs_cpu_path)? {
+        Some(source) => source,
+        None => {
+            let mut output = Output::unsupported(&format!(
+                "No CPU frequency information is available: there are no cpufreq policies in {}, and no cpu MHz lines in /proc/cpuinfo",
+                opt.sysfs_cpu_path.display()
+            ));
+            output.class.splice(0..0, classes);
+
+            return opt.common.build(move |_| Ok(output.clone()))?.run();
+        }
+    };
+    let topology = Topology::read_from(&opt.sysfs_cpu_path)?;
+    let cluster = match &opt.cluster {
+        Some(name) => Some(topology.cluster(name)?.clone()),
+        None => None,
+    };
 
     // Set up the history. We'll constrain the sparkline to a maximum of 10
     // historical values.
     let mut history = History::new(opt.sparkline.min(10) as usize);
+    let style = opt.common.style;
+    let format = match opt.format {
+        Some(template) => template,
+        None if opt.per_core => "{core_sparkline}".parse()?,
+        None => "{sparkline}".parse()?,
+    };
+    // The settings that the driver supports, along with the values that they
+    // can be changed to. These don't change while we're running.
+    let mut settings = Vec::new();
+    for setting in SETTINGS.iter() {
+        if let Some(policy) = source
+            .policies()
+            .iter()
+            .find(|policy| policy.is_active() && matches!(policy.setting(*setting), Ok(Some(_))))
+        {
+            settings.push((*setting, policy.available(*setting)?.join(", ")));
+        }
+    }
+    let turbo = Turbo::detect(&opt.sysfs_cpu_path);
+    let has_stats = Stats::read(source.policies())?.is_some();
+    let mut window = Window::new(opt.sparkline.min(10) as usize);
+    let mut residency: Option<Residency> = None;
+    let mut last_counts = Counts::read(&opt.sysfs_cpu_path)?;
+    let mut throttled = Throttled::default();
+    let sysfs_cpu_path = opt.sysfs_cpu_path.clone();
+    let tooltip_format = match opt.tooltip_format {
+        Some(template) => template,
+        None => default_tooltip(
+            topology.is_hybrid(),
+            &settings
+                .iter()
+                .map(|(setting, _)| *setting)
+                .collect::<Vec<Setting>>(),
+            turbo.is_some(),
+            last_counts.is_some(),
+            has_stats,
+        )
+        .parse()?,
+    };
+    let mut thresholds = opt.common.thresholds();
+    let mut gaps = opt.common.gaps();
+
+    opt.common
+        .build(|event| {
+            // Residency and throttling are both worked out from counters, so
+            // after a suspend, they start again from the current counters, and
+            // the last values are shown until there's something new. The
+            // counters and the history are only sampled on ticks, so that
+            // refreshes don't shorten the period they cover.
+            let refresh = event == Event::Refresh;
+            let gap = !refresh && gaps.sample();
+            let readings = source.read()?;
+            let overall = Summary::new(readings.iter())
+                .ok_or_else(|| anyhow!("no cores with cpufreq support"))?;
+
+            // The main percentage comes from the selected cluster, if any, but
+            // the other placeholders always cover every core.
+            let perc = match &cluster {
+                Some(cluster) => Summary::new(
+                    readings
+                        .iter()
+                        .filter(|reading| cluster.contains(reading.id)),
+                )
+                .map(|summary| summary.percentage())
+                .unwrap_or_default(),
+                None => overall.percentage(),
+            };
 
-    Loop::new(
-        || {
-            let freqs = cores
+            if !refresh {
+                history.push(perc);
+            }
+
+            let clusters: Vec<(&str, String, Option<Summary>)> = topology
+                .clusters()
                 .iter()
-                .map(|core| Ok(core.current_freq()?))
-                .collect::<Result<Vec<Frequency>>>()?;
-            let core_count = cores.len() as u64;
-            let max_freq = max_freq(&cores)?.unwrap_or_else(|| *freqs.iter().max().unwrap());
-            let avg_freq = freqs.iter().fold(0, |acc, freq| acc + freq) / core_count;
-            let perc = Percentage::calculate(avg_freq as f64, max_freq as f64);
-
-            history.push(perc);
-
-            Ok(Output {
-                text: history.to_string(|p| p.as_u8()),
-                tooltip: format_tooltip(&freqs),
-                class: classes.clone(),
-                percentage: perc.as_u8().into(),
-            })
-        },
-        &opt.interval.into(),
-    )
-    .run()?;
+                .map(|cluster| {
+                    (
+                        cluster.label.as_str(),
+                        cluster.name.clone(),
+                        Summary::new(
+                            readings
+                                .iter()
+                                .filter(|reading| cluster.contains(reading.id)),
+                        ),
+                    )
+                })
+                .collect();
+
+            let mut vars = Vars::new()
+                .with("percentage", perc)
+                .with("avg", FrequencyDisplay(overall.avg).to_string())
+                .with("min", FrequencyDisplay(overall.min).to_string())
+                .with("max", FrequencyDisplay(overall.max).to_string())
+                .with("count", overall.count)
+                .with("cores", format_cores(overall.count))
+                .with(
+                    "offline",
+                    source.policies().iter().map(Policy::offline).sum::<usize>(),
+                )
+                .with("source", source.name())
+                .with("cluster_table", cluster_table(&clusters))
+                .with("core_table", core_table(&readings))
+                .with("core_sparkline", core_sparkline(&readings, &style))
+                .with("sparkline", history.render(&style, |p| p.as_u8()));
+            for (_, name, summary) in clusters.iter() {
+                if let Some(summary) = summary {
+                    vars = vars.with(
+                        &format!("cluster_{}", name),
+                        FrequencyDisplay(summary.avg).to_string(),
+                    );
+                }
+            }
+
+            let mut class = classes.clone();
+            for (setting, available) in settings.iter() {
+                let values = setting_values(source.policies(), *setting)?;
+                class.extend(
+                    values
+                        .iter()
+                        .map(|value| format!("{}-{}", setting.name(), value)),
+                );
+                vars = vars.with(setting.name(), values.join(", ")).with(
+                    &format!("available_{}s", setting.name()),
+                    available.as_str(),
+                );
+            }
+
+            if has_stats {
+                if gap {
+                    window.clear();
+                }
+                if !refresh {
+                    match Stats::read(source.policies())? {
+                        Some(stats) => {
+                            if let Some(latest) = window.push(stats) {
+                                residency = Some(latest);
+                            }
+                        }
+                        None => residency = None,
+                    }
+                }
+                vars = vars
+                    .with(
+                        "residency",
+                        residency
+                            .as_ref()
+                            .map(|residency| residency.histogram())
+                            .unwrap_or_default(),
+                    )
+                    .with(
+                        "transitions",
+                        residency
+                            .as_ref()
+                            .map(|residency| residency.transitions)
+                            .unwrap_or_default(),
+                    );
+            }
+
+            if let Some(turbo) = &turbo {
+                let enabled = turbo.enabled()?;
+                if enabled {
+                    class.push("turbo".into());
+                }
+                vars = vars.with("turbo", if enabled { "on" } else { "off" });
+            }
+
+            // Throttling is counted since the last update, so the first
+            // update can't report any.
+            if last_counts.is_some() {
+                if !refresh {
+                    let counts = Counts::read(&sysfs_cpu_path)?;
+                    match (&counts, &last_counts) {
+                        (Some(_), Some(_)) if gap => {}
+                        (Some(counts), Some(last)) => throttled = counts.since(last),
+                        _ => throttled = Throttled::default(),
+                    }
+                    last_counts = counts;
+                }
+                if throttled.any() {
+                    class.push("throttled".into());
+                }
+                vars = vars
+                    .with("throttled_cores", throttled.cores)
+                    .with("throttled_packages", throttled.packages)
+                    .with("throttled", throttled.describe());
+            }
+
+            let mut output = Output {
+                text: format.render(&vars),
+                tooltip: tooltip_format.render_optional(&vars),
+                class,
+                percentage: Some(perc.as_u8().into()),
+                ..Output::default()
+            };
+
+            thresholds.apply(perc, &mut output);
+            Ok(output)
+        })?
+        .run()?;
 
     Ok(())
 }
 
-#[derive(Default, Debug, Clone, Copy, Eq, Ord, PartialOrd, PartialEq)]
-struct FrequencyDisplay(Frequency);
+/// Builds the default tooltip, which only includes the clusters, settings, and
+/// statistics that the CPU actually has.
+fn default_tooltip(
+    hybrid: bool,
+    settings: &[Setting],
+    turbo: bool,
+    throttle: bool,
+    stats: bool,
+) -> String {
+    let mut tooltip = String::from("{cores}; ranging from {min} to {max}");
+    for setting in settings.iter() {
+        tooltip.push_str(match setting {
+            Setting::Governor => "\nGovernor: {governor} (available: {available_governors})",
+            Setting::Epp => "\nEPP: {epp} (available: {available_epps})",
+        });
+    }
+    if turbo {
+        tooltip.push_str("\nTurbo: {turbo}");
+    }
+    if throttle {
+        tooltip.push_str("\nThermal: {throttled}");
+    }
+    if hybrid {
+        tooltip.push_str("\n\n{cluster_table}");
+    }
+    tooltip.push_str("\n\n{core_table}");
+    if stats {
+        tooltip.push_str("\n\n{residency}\n{transitions:.1} transitions/s");
+    }
 
-impl FrequencyDisplay {
-    fn to_string(&self) -> String {
-        let f = self.0 as f64;
+    tooltip
+}
 
-        if f > 1_000_000. {
-            format!("{:.2} GHz", f / 1_000_000.)
-        } else if f > 1_000. {
-            format!("{:.0} MHz", f / 1_000.)
-        } else {
-            format!("{} kHz", self.0)
+/// Returns the distinct values of a setting across the policies that support
+/// it, in the order they're first seen. There's usually only one.
+fn setting_values(policies: &[Policy], setting: Setting) -> Result<Vec<String>> {
+    let mut values: Vec<String> = Vec::new();
+    for policy in policies.iter().filter(|policy| policy.is_active()) {
+        if let Some(value) = policy.setting(setting)? {
+            if !values.contains(&value) {
+                values.push(value);
+            }
         }
-        .to_string()
     }
+
+    Ok(values)
 }
 
+#[derive(Default, Debug, Clone, Copy, Eq, Ord, PartialOrd, PartialEq)]
+struct FrequencyDisplay(Frequency);
+
 impl std::ops::Deref for FrequencyDisplay {
     type Target = Frequency;
 
@@ -99,30 +375,206 @@ impl std::ops::Deref for FrequencyDisplay {
 
 impl fmt::Display for FrequencyDisplay {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.to_string())
+        let freq = self.0 as f64;
+
+        if freq > 1_000_000. {
+            write!(f, "{:.2} GHz", freq / 1_000_000.)
+        } else if freq > 1_000. {
+            write!(f, "{:.0} MHz", freq / 1_000.)
+        } else {
+            write!(f, "{} kHz", self.0)
+        }
     }
 }
 
-fn format_tooltip(freqs: &Vec<Frequency>) -> String {
-    let count = freqs.len();
-
+fn format_cores(count: usize) -> String {
     format!(
-        "{} core{}; ranging from {} to {}",
+        "{} core{}",
         count,
         match count {
             1 => "",
             _ => "s",
         },
-        FrequencyDisplay(*freqs.iter().min().unwrap()),
-        FrequencyDisplay(*freqs.iter().max().unwrap()),
     )
 }
 
-fn max_freq(cores: &Vec<CPU>) -> Result<Option<Frequency>> {
-    Ok(cores
+/// Summary is the range of frequencies across a set of cores, along with the
+/// highest frequency any of them can reach.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Summary {
+    avg: Frequency,
+    min: Frequency,
+    max: Frequency,
+    limit: Frequency,
+    count: usize,
+}
+
+impl Summary {
+    /// Summarises the readings, or returns None if there aren't any.
+    fn new<'a, I>(readings: I) -> Option<Self>
+    where
+        I: Iterator<Item = &'a Reading>,
+    {
+        let readings: Vec<&Reading> = readings.collect();
+        if readings.is_empty() {
+            return None;
+        }
+
+        let count = readings.len();
+        Some(Self {
+            avg: readings.iter().map(|r| r.current).sum::<Frequency>() / count as u64,
+            min: readings.iter().map(|r| r.current).min()?,
+            max: readings.iter().map(|r| r.current).max()?,
+            limit: readings.iter().map(|r| r.max).max()?,
+            count,
+        })
+    }
+
+    /// Returns the average frequency relative to the highest frequency.
+    fn percentage(&self) -> Percentage {
+        Percentage::calculate(self.avg as f64, self.limit as f64)
+    }
+}
+
+/// Renders a table with a line for each core, showing its current frequency
+/// and the range it can run at.
+fn core_table(readings: &[Reading]) -> String {
+    let current: Vec<String> = readings
+        .iter()
+        .map(|reading| FrequencyDisplay(reading.current).to_string())
+        .collect();
+    let id_width = readings
         .iter()
-        .map(|core| Ok(core.max_freq()?))
-        .collect::<Result<Vec<Frequency>>>()?
-        .into_iter()
-        .max())
+        .map(|reading| reading.id.to_string().len())
+        .max()
+        .unwrap_or_default();
+    let freq_width = current.iter().map(String::len).max().unwrap_or_default();
+
+    readings
+        .iter()
+        .zip(current.iter())
+        .map(|(reading, current)| {
+            format!(
+                "CPU{:<id_width$} {:>freq_width$} ({} to {})",
+                reading.id,
+                current,
+                FrequencyDisplay(reading.min),
+                FrequencyDisplay(reading.max),
+                id_width = id_width,
+                freq_width = freq_width
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Renders each core's current frequency, relative to its maximum, as one
+/// value in a sparkline.
+fn core_sparkline<R>(readings: &[Reading], renderer: &R) -> String
+where
+    R: Renderer,
+{
+    renderer.render(
+        &readings
+            .iter()
+            .map(|reading| reading.percentage().as_u8())
+            .collect::<Vec<u8>>(),
+    )
+}
+
+/// Renders a table with the average frequency of each cluster.
+fn cluster_table(clusters: &[(&str, String, Option<Summary>)]) -> String {
+    let width = clusters
+        .iter()
+        .filter(|(_, _, summary)| summary.is_some())
+        .map(|(label, _, _)| label.len())
+        .max()
+        .unwrap_or_default();
+
+    clusters
+        .iter()
+        .filter_map(|(label, _, summary)| {
+            summary.map(|summary| {
+                format!(
+                    "{:<width$} {}",
+                    label,
+                    FrequencyDisplay(summary.avg),
+                    width = width
+                )
+            })
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let readings = [
+            Reading {
+                id: 0,
+                current: 4_000_000,
+                min: 800_000,
+                max: 5_000_000,
+            },
+            Reading {
+                id: 1,
+                current: 1_000_000,
+                min: 800_000,
+                max: 3_000_000,
+            },
+        ];
+
+        let all = Summary::new(readings.iter()).unwrap();
+        assert_eq!(2_500_000, all.avg);
+        assert_eq!(50, all.percentage().as_u8());
+
+        let e = Summary::new(readings.iter().filter(|r| r.id == 1));
+        assert_eq!(33, e.unwrap().percentage().as_u8());
+        assert_eq!(None, Summary::new(readings.iter().filter(|r| r.id == 2)));
+
+        assert_eq!(
+            "P-cores 4.00 GHz\nE-cores 1000 MHz",
+            cluster_table(&[
+                ("P-cores", "p".into(), Summary::new(readings[..1].iter())),
+                ("E-cores", "e".into(), e),
+                ("Cluster 2", "cluster2".into(), None),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_default_tooltip() {
+        assert_eq!(
+            "{cores}; ranging from {min} to {max}\n\n{core_table}",
+            default_tooltip(false, &[], false, false, false)
+        );
+        assert_eq!(
+            "{cores}; ranging from {min} to {max}\nEPP: {epp} (available: {available_epps})\nTurbo: {turbo}\nThermal: {throttled}\n\n{cluster_table}\n\n{core_table}",
+            default_tooltip(true, &[Setting::Epp], true, true, false)
+        );
+    }
+
+    #[test]
+    fn test_cores() {
+        let readings: Vec<Reading> = [(3_000_000, 3_000_000), (900_000, 3_000_000)]
+            .iter()
+            .enumerate()
+            .map(|(id, (current, max))| Reading {
+                id: id * 5,
+                current: *current,
+                min: 400_000,
+                max: *max,
+            })
+            .collect();
+
+        assert_eq!(
+            "CPU0 3.00 GHz (400 MHz to 3.00 GHz)\nCPU5  900 MHz (400 MHz to 3.00 GHz)",
+            core_table(&readings)
+        );
+        assert_eq!("█▃", core_sparkline(&readings, &waybar::Blocks));
+    }
 }
@@ -0,0 +1,248 @@
+use anyhow::{anyhow, Result};
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use waybar::Percentage;
+
+use crate::cpu::{self, Files, Frequency, Policy};
+
+/// Reading is the current frequency of a single core, along with the range it
+/// can run at.
+#[derive(Debug, Clone, Copy)]
+pub struct Reading {
+    pub id: usize,
+    pub current: Frequency,
+    pub min: Frequency,
+    pub max: Frequency,
+}
+
+impl Reading {
+    pub fn percentage(&self) -> Percentage {
+        Percentage::calculate(self.current as f64, self.max as f64)
+    }
+}
+
+/// Source is somewhere that the current frequency of each core can be read
+/// from.
+pub trait Source: Debug {
+    /// Describes where the frequencies come from.
+    fn name(&self) -> &'static str;
+
+    /// Reads the current frequency of each online core, in order.
+    fn read(&mut self) -> Result<Vec<Reading>>;
+
+    /// Returns the cpufreq policies found by the last read, if the source has
+    /// them.
+    fn policies(&self) -> &[Policy] {
+        &[]
+    }
+}
+
+/// Picks the first source that works on this system: the scaling frequencies
+/// in sysfs, then the hardware frequencies in sysfs, then /proc/cpuinfo.
+/// Returns None if none of them do.
+pub fn detect(sysfs_cpu_path: &Path) -> Result<Option<Box<dyn Source>>> {
+    for files in [Files::Scaling, Files::Cpuinfo].iter() {
+        if let Some(source) = Sysfs::probe(sysfs_cpu_path, *files) {
+            return Ok(Some(Box::new(source)));
+        }
+    }
+
+    Ok(ProcCpuinfo::probe(Path::new(PROC_CPUINFO))
+        .map(|source| Box::new(source) as Box<dyn Source>))
+}
+
+/// Sysfs reads the frequencies of each cpufreq policy.
+#[derive(Debug)]
+pub struct Sysfs {
+    path: PathBuf,
+    files: Files,
+    policies: Vec<Policy>,
+}
+
+impl Sysfs {
+    /// Returns a source if there's at least one active policy, and its
+    /// current frequency can actually be read: cpuinfo_cur_freq usually
+    /// exists, but can only be read by root.
+    fn probe(path: &Path, files: Files) -> Option<Self> {
+        let mut source = Self {
+            path: path.to_path_buf(),
+            files,
+            policies: Vec::new(),
+        };
+
+        match source.read() {
+            Ok(readings) if !readings.is_empty() => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl Source for Sysfs {
+    fn name(&self) -> &'static str {
+        self.files.current()
+    }
+
+    /// Reads each policy, giving a reading for each of its online CPUs.
+    /// Inactive policies, and those whose CPUs have gone offline since they
+    /// were discovered, are skipped.
+    fn read(&mut self) -> Result<Vec<Reading>> {
+        // CPUs can be brought online or taken offline at any time, so we look
+        // for policies afresh on every update.
+        self.policies = Policy::discover(&self.path, self.files)?;
+
+        let mut readings = Vec::new();
+        for policy in self.policies.iter().filter(|policy| policy.is_active()) {
+            let read = || -> cpu::Result<(Frequency, Frequency, Frequency)> {
+                Ok((
+                    policy.current_freq()?,
+                    policy.min_freq()?,
+                    policy.max_freq()?,
+                ))
+            };
+
+            match read() {
+                Ok((current, min, max)) => {
+                    readings.extend(policy.cpus().iter().map(|id| Reading {
+                        id: *id,
+                        current,
+                        min,
+                        max,
+                    }))
+                }
+                Err(e) if e.is_offline() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        readings.sort_by_key(|reading| reading.id);
+
+        Ok(readings)
+    }
+
+    fn policies(&self) -> &[Policy] {
+        &self.policies
+    }
+}
+
+const PROC_CPUINFO: &str = "/proc/cpuinfo";
+
+/// ProcCpuinfo reads the "cpu MHz" lines from /proc/cpuinfo, which x86 VMs
+/// and some ARM boards have even without cpufreq. There's no way to know the
+/// range the cores can run at, so the range is the one we've seen so far.
+#[derive(Debug)]
+pub struct ProcCpuinfo {
+    path: PathBuf,
+    min: Frequency,
+    max: Frequency,
+}
+
+impl ProcCpuinfo {
+    fn probe(path: &Path) -> Option<Self> {
+        let mut source = Self {
+            path: path.to_path_buf(),
+            min: Frequency::MAX,
+            max: 0,
+        };
+
+        match source.read() {
+            Ok(readings) if !readings.is_empty() => Some(source),
+            _ => None,
+        }
+    }
+
+    /// Parses the processor numbers and frequencies out of /proc/cpuinfo,
+    /// converting the frequencies to kHz.
+    fn parse(cpuinfo: &str) -> Result<Vec<(usize, Frequency)>> {
+        let mut id = None;
+        let mut freqs = Vec::new();
+
+        for line in cpuinfo.lines() {
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+
+            match key {
+                "processor" => id = Some(value.parse()?),
+                "cpu MHz" => {
+                    let id = id.ok_or_else(|| anyhow!("cpu MHz before processor in cpuinfo"))?;
+                    freqs.push((id, (value.parse::<f64>()? * 1000.) as Frequency));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(freqs)
+    }
+}
+
+impl Source for ProcCpuinfo {
+    fn name(&self) -> &'static str {
+        PROC_CPUINFO
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>> {
+        let freqs = Self::parse(&fs::read_to_string(&self.path)?)?;
+        for (_, freq) in freqs.iter() {
+            self.min = self.min.min(*freq);
+            self.max = self.max.max(*freq);
+        }
+
+        Ok(freqs
+            .into_iter()
+            .map(|(id, current)| Reading {
+                id,
+                current,
+                min: self.min,
+                max: self.max,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use waybar::testing::Tree;
+
+    #[test]
+    fn test_proc_cpuinfo() {
+        let root = Tree::new("cpufreq-cpuinfo");
+        let path = root.join("cpuinfo");
+        root.write(
+            "cpuinfo",
+            "processor\t: 0
+model name\t: Intel(R) Xeon(R) Processor
+cpu MHz\t\t: 2100.000
+
+processor\t: 1
+cpu MHz\t\t: 800.500
+",
+        );
+
+        let mut source = ProcCpuinfo::probe(&path).unwrap();
+        let readings = source.read().unwrap();
+        assert_eq!(2, readings.len());
+        assert_eq!(800_500, readings[1].current);
+        assert_eq!(2_100_000, readings[1].max);
+        assert_eq!(100, readings[0].percentage().as_u8());
+
+        root.write("cpuinfo", "processor\t: 0\n");
+        assert!(ProcCpuinfo::probe(&path).is_none());
+        assert!(ProcCpuinfo::parse("cpu MHz : 100").is_err());
+    }
+
+    #[test]
+    fn test_detect() {
+        let root = Tree::new("cpufreq-detect");
+        root.write("cpufreq/policy0/affected_cpus", "0\n");
+        for file in ["cpuinfo_cur_freq", "cpuinfo_min_freq", "cpuinfo_max_freq"].iter() {
+            root.write(format!("cpufreq/policy0/{}", file), "1000000\n");
+        }
+
+        // No scaling files, so it should fall back to the hardware frequency.
+        let mut source = detect(root.path()).unwrap().unwrap();
+        assert_eq!("cpuinfo_cur_freq", source.name());
+        assert_eq!(1, source.read().unwrap().len());
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Instant;
+use waybar::Percentage;
+
+use crate::cpu::{Frequency, Policy};
+use crate::FrequencyDisplay;
+
+/// The width of the bars in the residency histogram, in characters.
+const BAR_WIDTH: usize = 20;
+
+/// Stats are the cumulative cpufreq statistics across every active policy: the
+/// time spent at each frequency, and the number of frequency transitions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    time: BTreeMap<Frequency, u64>,
+    transitions: u64,
+    at: Instant,
+}
+
+impl Stats {
+    /// Reads the statistics, or returns None if none of the policies have
+    /// them: they're only kept if the kernel was built with
+    /// CONFIG_CPU_FREQ_STAT, and drivers like intel_pstate that pick their
+    /// own frequencies don't keep them at all.
+    pub fn read(policies: &[Policy]) -> Result<Option<Self>> {
+        let mut stats = None;
+
+        for policy in policies.iter().filter(|policy| policy.is_active()) {
+            if let (Some(time), Some(transitions)) =
+                (policy.time_in_state()?, policy.total_trans()?)
+            {
+                let stats = stats.get_or_insert_with(|| Self {
+                    time: BTreeMap::new(),
+                    transitions: 0,
+                    at: Instant::now(),
+                });
+
+                for (freq, time) in time {
+                    *stats.time.entry(freq).or_default() += time;
+                }
+                stats.transitions += transitions;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Works out where the time went since the last statistics. Counters can
+    /// go backwards if the statistics are reset or a policy goes away, in
+    /// which case those frequencies are treated as unused.
+    pub fn since(&self, last: &Stats) -> Residency {
+        let deltas: Vec<(Frequency, u64)> = self
+            .time
+            .iter()
+            .map(|(freq, time)| {
+                let last = last.time.get(freq).copied().unwrap_or_default();
+                (*freq, time.saturating_sub(last))
+            })
+            .collect();
+        let total: u64 = deltas.iter().map(|(_, time)| time).sum();
+        let elapsed = self.at.duration_since(last.at).as_secs_f64();
+
+        Residency {
+            shares: deltas
+                .into_iter()
+                .rev()
+                .map(|(freq, time)| (freq, Percentage::calculate(time as f64, total as f64)))
+                .collect(),
+            transitions: if elapsed > 0. {
+                self.transitions.saturating_sub(last.transitions) as f64 / elapsed
+            } else {
+                0.
+            },
+        }
+    }
+}
+
+/// Window keeps the statistics from the last few updates, so residency can be
+/// calculated over the same period as the sparkline.
+#[derive(Debug)]
+pub struct Window {
+    stats: VecDeque<Stats>,
+    capacity: usize,
+}
+
+impl Window {
+    /// Creates a window covering the given number of updates.
+    pub fn new(updates: usize) -> Self {
+        let capacity = updates.max(1) + 1;
+
+        Self {
+            stats: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Adds the latest statistics, and returns the residency since the oldest
+    /// statistics in the window, if there are any.
+    pub fn push(&mut self, stats: Stats) -> Option<Residency> {
+        if self.stats.len() == self.capacity {
+            self.stats.pop_front();
+        }
+        let residency = self.stats.front().map(|oldest| stats.since(oldest));
+        self.stats.push_back(stats);
+
+        residency
+    }
+
+    /// Forgets all of the statistics, such as after a suspend, so that the
+    /// next residency isn't calculated across the gap.
+    pub fn clear(&mut self) {
+        self.stats.clear();
+    }
+}
+
+/// Residency is the share of time spent at each frequency, fastest first, and
+/// the number of frequency transitions per second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Residency {
+    pub shares: Vec<(Frequency, Percentage)>,
+    pub transitions: f64,
+}
+
+impl Residency {
+    /// Renders a histogram with a line for each frequency that was used.
+    pub fn histogram(&self) -> String {
+        let shares: Vec<(String, Percentage)> = self
+            .shares
+            .iter()
+            .filter(|(_, perc)| perc.as_u8() > 0)
+            .map(|(freq, perc)| (FrequencyDisplay(*freq).to_string(), *perc))
+            .collect();
+        let width = shares
+            .iter()
+            .map(|(freq, _)| freq.len())
+            .max()
+            .unwrap_or_default();
+
+        shares
+            .iter()
+            .map(|(freq, perc)| {
+                let bar = (perc.as_u8() as usize * BAR_WIDTH + 50) / 100;
+                format!(
+                    "{:>width$} {:<bar_width$} {:>3}%",
+                    freq,
+                    "█".repeat(bar),
+                    perc.as_u8(),
+                    width = width,
+                    bar_width = BAR_WIDTH
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn stats(time: &[(Frequency, u64)], transitions: u64, at: Instant) -> Stats {
+        Stats {
+            time: time.iter().copied().collect(),
+            transitions,
+            at,
+        }
+    }
+
+    #[test]
+    fn test_residency() {
+        let now = Instant::now();
+        let first = stats(&[(800_000, 100), (2_400_000, 100)], 10, now);
+        let second = stats(
+            &[(800_000, 175), (2_400_000, 125)],
+            30,
+            now + Duration::from_secs(2),
+        );
+
+        let residency = second.since(&first);
+        assert_eq!(
+            vec![(2_400_000, 25), (800_000, 75)],
+            residency
+                .shares
+                .iter()
+                .map(|(freq, perc)| (*freq, perc.as_u8()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(10., residency.transitions);
+        assert_eq!(
+            "2.40 GHz █████                 25%\n 800 MHz ███████████████       75%",
+            residency.histogram()
+        );
+
+        // Resets shouldn't produce nonsense.
+        let reset = stats(&[(800_000, 0), (2_400_000, 0)], 0, now);
+        assert_eq!("", reset.since(&first).histogram());
+    }
+
+    #[test]
+    fn test_window() {
+        let now = Instant::now();
+        let mut window = Window::new(2);
+
+        assert_eq!(None, window.push(stats(&[(800_000, 0)], 0, now)));
+        for (i, time) in [10, 20, 30].iter().enumerate() {
+            let residency = window
+                .push(stats(
+                    &[(800_000, *time)],
+                    *time,
+                    now + Duration::from_secs(i as u64 + 1),
+                ))
+                .unwrap();
+            assert_eq!(100, residency.shares[0].1.as_u8());
+            assert_eq!(10., residency.transitions);
+        }
+
+        window.clear();
+        assert_eq!(None, window.push(stats(&[(800_000, 1000)], 1000, now)));
+    }
+}
@@ -0,0 +1,12 @@
+use waybar::testing::Tree;
+
+/// Writes a cpufreq policy into a fake CPU sysfs tree, with all of its
+/// scaling frequencies at 1 GHz.
+pub(crate) fn policy(root: &Tree, id: usize, affected: &str, related: &str) {
+    let dir = format!("cpufreq/policy{}", id);
+    for file in ["scaling_cur_freq", "scaling_min_freq", "scaling_max_freq"].iter() {
+        root.write(format!("{}/{}", dir, file), "1000000\n");
+    }
+    root.write(format!("{}/affected_cpus", dir), affected);
+    root.write(format!("{}/related_cpus", dir), related);
+}
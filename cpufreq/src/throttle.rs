@@ -0,0 +1,185 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Counts are the thermal throttling event counters that x86 CPUs keep for
+/// each core and each package. Every CPU in a package reports the same package
+/// counter, so those are only kept once per package.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Counts {
+    cores: BTreeMap<usize, u64>,
+    packages: BTreeMap<usize, u64>,
+}
+
+/// Throttled is the number of cores and packages that were throttled between
+/// two sets of counts.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Throttled {
+    pub cores: usize,
+    pub packages: usize,
+}
+
+impl Counts {
+    /// Reads the counters under the CPU sysfs path, or returns None if there
+    /// aren't any, including if there's no CPU sysfs at all.
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        let mut counts = Self::default();
+
+        let dir = match path.read_dir() {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in dir {
+            let path = entry?.path();
+            let id = match path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("cpu"))
+                .and_then(|id| id.parse::<usize>().ok())
+            {
+                Some(id) => id,
+                None => continue,
+            };
+
+            // Offline CPUs don't have the directory at all.
+            let dir = path.join("thermal_throttle");
+            if !dir.is_dir() {
+                continue;
+            }
+
+            counts
+                .cores
+                .insert(id, counter(&dir.join("core_throttle_count"))?);
+
+            let package = fs::read_to_string(path.join("topology/physical_package_id"))
+                .ok()
+                .and_then(|id| id.trim().parse().ok())
+                .unwrap_or_default();
+            counts
+                .packages
+                .insert(package, counter(&dir.join("package_throttle_count"))?);
+        }
+
+        if counts.cores.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(counts))
+        }
+    }
+
+    /// Returns how many cores and packages were throttled since the last
+    /// counts. Cores and packages that weren't in the last counts are ignored.
+    pub fn since(&self, last: &Counts) -> Throttled {
+        let increased = |now: &BTreeMap<usize, u64>, then: &BTreeMap<usize, u64>| {
+            now.iter()
+                .filter(|(id, count)| matches!(then.get(id), Some(last) if *count > last))
+                .count()
+        };
+
+        Throttled {
+            cores: increased(&self.cores, &last.cores),
+            packages: increased(&self.packages, &last.packages),
+        }
+    }
+}
+
+impl Throttled {
+    pub fn any(&self) -> bool {
+        self.cores > 0 || self.packages > 0
+    }
+
+    /// Describes the throttling in a phrase like `2 cores and 1 package
+    /// throttled`, leaving out whichever wasn't throttled.
+    pub fn describe(&self) -> String {
+        let plural = |count: usize, noun: &str| {
+            format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+        };
+
+        match (self.cores, self.packages) {
+            (0, 0) => "not throttled".into(),
+            (cores, 0) => format!("{} throttled", plural(cores, "core")),
+            (0, packages) => format!("{} throttled", plural(packages, "package")),
+            (cores, packages) => format!(
+                "{} and {} throttled",
+                plural(cores, "core"),
+                plural(packages, "package")
+            ),
+        }
+    }
+}
+
+fn counter(path: &Path) -> Result<u64> {
+    Ok(fs::read_to_string(path)?.trim().parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use waybar::testing::Tree;
+
+    #[test]
+    fn test_counts() {
+        let root = Tree::new("cpufreq-throttle");
+        let cpu = |id: usize, package: usize, core_count: u64, package_count: u64| {
+            let dir = format!("cpu{}", id);
+            root.write(
+                format!("{}/topology/physical_package_id", dir),
+                &format!("{}\n", package),
+            );
+            root.write(
+                format!("{}/thermal_throttle/core_throttle_count", dir),
+                &format!("{}\n", core_count),
+            );
+            root.write(
+                format!("{}/thermal_throttle/package_throttle_count", dir),
+                &format!("{}\n", package_count),
+            );
+        };
+
+        root.write("cpufreq/boost", "1\n");
+        assert_eq!(None, Counts::read(root.path()).unwrap());
+
+        cpu(0, 0, 5, 10);
+        cpu(1, 0, 0, 10);
+        let last = Counts::read(root.path()).unwrap().unwrap();
+        assert!(!last.since(&last).any());
+
+        cpu(0, 0, 6, 11);
+        cpu(1, 0, 0, 11);
+        cpu(2, 1, 100, 100);
+        let throttled = Counts::read(root.path()).unwrap().unwrap().since(&last);
+        assert_eq!(
+            Throttled {
+                cores: 1,
+                packages: 1
+            },
+            throttled
+        );
+        assert!(throttled.any());
+        assert_eq!("1 core and 1 package throttled", throttled.describe());
+        assert_eq!("not throttled", Throttled::default().describe());
+        assert_eq!(
+            "2 cores throttled",
+            Throttled {
+                cores: 2,
+                packages: 0
+            }
+            .describe()
+        );
+        assert_eq!(
+            "1 package throttled",
+            Throttled {
+                cores: 0,
+                packages: 1
+            }
+            .describe()
+        );
+
+        // There's no CPU sysfs at all in some containers.
+        assert_eq!(None, Counts::read(&root.join("missing")).unwrap());
+    }
+}
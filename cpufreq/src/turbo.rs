@@ -0,0 +1,98 @@
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Turbo is where the state of turbo (or boost) can be read from, which
+/// depends on the driver.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Turbo {
+    /// intel_pstate has a global switch, which is inverted.
+    IntelPstate(PathBuf),
+
+    /// acpi-cpufreq, and amd-pstate on older kernels, have a global boost
+    /// switch.
+    Global(PathBuf),
+
+    /// amd-pstate on newer kernels has a boost switch in each policy.
+    Policies(PathBuf),
+}
+
+impl Turbo {
+    /// Finds the turbo switch under the CPU sysfs path, if there is one.
+    pub fn detect(path: &Path) -> Option<Self> {
+        let no_turbo = path.join("intel_pstate/no_turbo");
+        if no_turbo.exists() {
+            return Some(Turbo::IntelPstate(no_turbo));
+        }
+
+        let boost = path.join("cpufreq/boost");
+        if boost.exists() {
+            return Some(Turbo::Global(boost));
+        }
+
+        // amd-pstate reports whether it's in use at all, and which mode it's
+        // in; the per-policy switches only mean anything if it is.
+        let status = fs::read_to_string(path.join("amd_pstate/status")).unwrap_or_default();
+        if !matches!(status.trim(), "" | "disable") && path.join("cpufreq/policy0/boost").exists() {
+            return Some(Turbo::Policies(path.join("cpufreq")));
+        }
+
+        None
+    }
+
+    /// Returns true if turbo is enabled. With per-policy switches, that's if
+    /// any policy has it enabled.
+    pub fn enabled(&self) -> Result<bool> {
+        match self {
+            Turbo::IntelPstate(path) => Ok(!flag(path)?),
+            Turbo::Global(path) => flag(path),
+            Turbo::Policies(path) => {
+                // Inactive policies can't be read, and don't matter anyway.
+                for entry in path.read_dir()? {
+                    let entry = entry?;
+                    if entry.file_name().to_string_lossy().starts_with("policy")
+                        && matches!(flag(&entry.path().join("boost")), Ok(true))
+                    {
+                        return Ok(true);
+                    }
+                }
+
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Reads a sysfs file containing 0 or 1.
+fn flag(path: &Path) -> Result<bool> {
+    Ok(fs::read_to_string(path)?.trim() != "0")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use waybar::testing::Tree;
+
+    #[test]
+    fn test_turbo() {
+        let root = Tree::new("cpufreq-turbo");
+
+        assert_eq!(None, Turbo::detect(root.path()));
+
+        // amd-pstate's per-policy switches are ignored if it isn't in use.
+        root.write("amd_pstate/status", "disable\n");
+        root.write("cpufreq/policy0/boost", "0\n");
+        root.write("cpufreq/policy1/boost", "1\n");
+        assert_eq!(None, Turbo::detect(root.path()));
+        root.write("amd_pstate/status", "active\n");
+        let turbo = Turbo::detect(root.path()).unwrap();
+        assert!(matches!(turbo, Turbo::Policies(_)));
+        assert!(turbo.enabled().unwrap());
+
+        root.write("cpufreq/boost", "0\n");
+        assert!(!Turbo::detect(root.path()).unwrap().enabled().unwrap());
+
+        root.write("intel_pstate/no_turbo", "0\n");
+        assert!(Turbo::detect(root.path()).unwrap().enabled().unwrap());
+    }
+}
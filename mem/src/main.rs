@@ -1,16 +1,16 @@
-use anyhow::Result;
-use humantime::Duration;
-use std::{fs, str};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::PathBuf;
 use structopt::StructOpt;
-use waybar::{History, Loop, Output, Percentage};
+use waybar::{Cgroup, CommonOpt, Event, History, Output, Percentage, Recovery, Template, Vars};
 
 #[derive(Debug, StructOpt)]
 struct Opt {
     #[structopt(short, long, default_value = "mem", help = "CSS class")]
     class: String,
 
-    #[structopt(short, long, default_value = "1s", help = "interval between updates")]
-    interval: Duration,
+    #[structopt(flatten)]
+    common: CommonOpt,
 
     #[structopt(
         short,
@@ -20,48 +20,131 @@ struct Opt {
         help = "enable sparkline with N historical values"
     )]
     sparkline: u8,
+
+    #[structopt(
+        long,
+        name = "CGROUP",
+        help = "report usage against the limit of a cgroup v2 cgroup [default: this process's cgroup]"
+    )]
+    cgroup: Option<Option<PathBuf>>,
+
+    #[structopt(
+        long,
+        default_value = "{sparkline}",
+        help = "format for the module text"
+    )]
+    format: Template,
+
+    #[structopt(long, default_value = "{percentage}%", help = "format for the tooltip")]
+    tooltip_format: Template,
 }
 
+const KIB_PER_GIB: f64 = 1024. * 1024.;
+const BYTES_PER_KIB: f64 = 1024.;
+
 fn main() -> Result<()> {
     let opt = Opt::from_args();
-    let classes = vec![opt.class.into()];
+    let classes = vec![opt.class];
 
     // Set up the history. We'll constrain the sparkline to a maximum of 10
     // historical values.
     let mut history = History::new(opt.sparkline.min(10) as usize);
+    let style = opt.common.style;
+    let format = opt.format;
+    let tooltip_format = opt.tooltip_format;
+    let mut thresholds = opt.common.thresholds();
+    let cgroup = match opt.cgroup {
+        Some(Some(path)) => Some(Cgroup::new(path)?),
+        Some(None) => Some(Cgroup::own()?),
+        None => None,
+    };
 
-    Loop::new(
-        || {
-            let mut available = None;
-            let mut total = None;
-
-            for line in str::from_utf8(fs::read("/proc/meminfo")?.as_slice())?.split("\n") {
-                if line.starts_with("MemTotal:") {
-                    total = Some(line.split_whitespace().nth(1).unwrap().parse::<u64>()?);
-                } else if line.starts_with("MemAvailable:") {
-                    available = Some(line.split_whitespace().nth(1).unwrap().parse::<u64>()?);
-                }
+    opt.common
+        .build(|event| {
+            let meminfo =
+                fs::read_to_string("/proc/meminfo").context("cannot read /proc/meminfo")?;
+            let mut total = field(&meminfo, "MemTotal")? as f64;
+            let mut available = field(&meminfo, "MemAvailable")? as f64;
 
-                if available.is_some() && total.is_some() {
-                    break;
+            // A cgroup without a limit of its own can use all of the memory on
+            // the machine, but only its own usage counts.
+            if let Some(cgroup) = &cgroup {
+                if let Some(max) = cgroup.memory_max()? {
+                    total = total.min(max as f64 / BYTES_PER_KIB);
                 }
+                available = (total - cgroup.memory_used()? as f64 / BYTES_PER_KIB).max(0.);
             }
 
-            let available = available.unwrap() as f64;
-            let total = total.unwrap() as f64;
             let perc = Percentage::calculate(total - available, total);
 
-            history.push(perc);
-            Ok(Output {
+            // Refreshes don't add to the history, so that it still covers
+            // the last N intervals.
+            if event != Event::Refresh {
+                history.push(perc);
+            }
+
+            // /proc/meminfo is in kB (well, KiB), but GiB is more useful for
+            // display purposes.
+            let vars = Vars::new()
+                .with("percentage", perc)
+                .with("used", (total - available) / KIB_PER_GIB)
+                .with("available", available / KIB_PER_GIB)
+                .with("total", total / KIB_PER_GIB)
+                .with("sparkline", history.render(&style, |p| p.as_u8()));
+
+            let mut output = Output {
                 class: classes.clone(),
-                percentage: perc.as_u8().into(),
-                tooltip: format!("{}", perc),
-                text: history.to_string(|p| p.as_u8()),
-            })
-        },
-        &opt.interval.into(),
-    )
-    .run()?;
+                percentage: Some(perc.as_u8().into()),
+                tooltip: tooltip_format.render_optional(&vars),
+                text: format.render(&vars),
+                ..Output::default()
+            };
+
+            thresholds.apply(perc, &mut output);
+            Ok(output)
+        })?
+        .fatal_if(Recovery::not_found)
+        .run()?;
 
     Ok(())
 }
+
+/// Returns the value of a field in /proc/meminfo, in KiB.
+fn field(meminfo: &str, name: &str) -> Result<u64> {
+    let value = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+        .ok_or_else(|| anyhow!("no {} in /proc/meminfo", name))?;
+
+    value
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .parse()
+        .with_context(|| format!("malformed {} in /proc/meminfo", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field() {
+        let meminfo = "MemTotal:       32562232 kB
+MemFree:         1209076 kB
+MemAvailable:   20484096 kB
+Buffers:         1062520 kB
+";
+
+        assert_eq!(32562232, field(meminfo, "MemTotal").unwrap());
+        assert_eq!(20484096, field(meminfo, "MemAvailable").unwrap());
+        assert_eq!(
+            "no Cached in /proc/meminfo",
+            field(meminfo, "Cached").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "malformed MemTotal in /proc/meminfo",
+            field("MemTotal: kB\n", "MemTotal").unwrap_err().to_string()
+        );
+    }
+}
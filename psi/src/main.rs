@@ -0,0 +1,257 @@
+use anyhow::Result;
+use humantime::Duration;
+use std::thread;
+use structopt::StructOpt;
+use waybar::{
+    CommonOpt, Event, History, Module, Output, Percentage, Style, Template, Thresholds, Trigger,
+    Vars,
+};
+
+mod monitor;
+use monitor::Monitor;
+
+mod pressure;
+use pressure::{Kind, Pressure, Resource};
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(short, long, default_value = "psi", help = "CSS class")]
+    class: String,
+
+    #[structopt(flatten)]
+    common: CommonOpt,
+
+    #[structopt(
+        short,
+        long,
+        default_value = "5",
+        name = "N",
+        help = "enable sparkline with N historical values"
+    )]
+    sparkline: u8,
+
+    #[structopt(
+        long,
+        help = "resource to report: cpu, memory, or io [default: whichever is under the most pressure]"
+    )]
+    resource: Option<Resource>,
+
+    #[structopt(
+        long,
+        help = "report the time all non-idle tasks were stalled, rather than the time some were"
+    )]
+    full: bool,
+
+    #[structopt(
+        long,
+        help = "also refresh immediately when tasks are stalled for this long within the trigger window"
+    )]
+    trigger_stall: Option<Duration>,
+
+    #[structopt(long, default_value = "2s", help = "window for --trigger-stall")]
+    trigger_window: Duration,
+
+    #[structopt(
+        long,
+        default_value = "{sparkline}",
+        help = "format for the module text"
+    )]
+    format: Template,
+
+    #[structopt(long, default_value = "{table}", help = "format for the tooltip")]
+    tooltip_format: Template,
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    let window = *opt.trigger_window;
+
+    let psi = Psi {
+        class: opt.class,
+        resources: match opt.resource {
+            Some(resource) => vec![resource],
+            None => Resource::ALL.to_vec(),
+        },
+        kind: if opt.full { Kind::Full } else { Kind::Some },
+        trigger: opt.trigger_stall.map(|stall| (*stall, window)),
+        // We'll constrain the sparkline to a maximum of 10 historical values.
+        history: History::new(opt.sparkline.min(10) as usize),
+        style: opt.common.style,
+        thresholds: opt.common.thresholds(),
+        format: opt.format,
+        tooltip_format: opt.tooltip_format,
+    };
+
+    opt.common.build(psi)?.run()
+}
+
+struct Psi {
+    class: String,
+    resources: Vec<Resource>,
+    kind: Kind,
+    trigger: Option<(std::time::Duration, std::time::Duration)>,
+    history: History<Percentage>,
+    style: Style,
+    thresholds: Thresholds,
+    format: Template,
+    tooltip_format: Template,
+}
+
+impl Module for Psi {
+    fn start(&mut self, trigger: &Trigger) -> Result<()> {
+        let (stall, window) = match self.trigger {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+
+        // Triggers are only an optimisation on top of the interval, so if any
+        // can't be registered, we'll log why and carry on polling without
+        // them, rather than failing the whole module.
+        let monitors = match self
+            .resources
+            .iter()
+            .map(|resource| Monitor::new(*resource, self.kind, stall, window))
+            .collect::<Result<Vec<Monitor>>>()
+        {
+            Ok(monitors) => monitors,
+            Err(e) => {
+                eprintln!("psi: falling back to polling: {:#}", e);
+                return Ok(());
+            }
+        };
+
+        for mut monitor in monitors {
+            let trigger = trigger.clone();
+
+            // If the trigger goes away, we'll still have the interval to fall
+            // back on, so there's no need to do anything more than stop.
+            thread::spawn(move || {
+                while monitor.block_until_event().is_ok() && trigger.refresh().is_ok() {}
+            });
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self, event: Event) -> Result<Output> {
+        let mut pressures = Vec::with_capacity(Resource::ALL.len());
+        for resource in Resource::ALL.iter() {
+            pressures.push((*resource, Pressure::read(*resource)?));
+        }
+
+        // Report whichever of the selected resources is under the most
+        // pressure.
+        let (resource, avg10) = pressures
+            .iter()
+            .filter(|(resource, _)| self.resources.contains(resource))
+            .map(|(resource, pressure)| (*resource, pressure.get(self.kind).avg10))
+            .fold((self.resources[0], 0.), |max, current| {
+                if current.1 > max.1 {
+                    current
+                } else {
+                    max
+                }
+            });
+
+        // Triggers can refresh us far more often than the interval, so
+        // refreshes don't add to the history.
+        let perc = Percentage::from((avg10 / 100.).min(1.));
+        if event != Event::Refresh {
+            self.history.push(perc);
+        }
+
+        let mut vars = Vars::new()
+            .with("percentage", perc)
+            .with("resource", resource.name())
+            .with("sparkline", self.history.render(&self.style, |p| p.as_u8()))
+            .with("table", table(&pressures));
+        for (resource, pressure) in pressures.iter() {
+            for kind in [Kind::Some, Kind::Full].iter() {
+                let name = format!("{}_{}", resource.name(), kind.name());
+                let averages = pressure.get(*kind);
+
+                vars = vars
+                    .with(&name, averages.avg10)
+                    .with(&format!("{}60", name), averages.avg60)
+                    .with(&format!("{}300", name), averages.avg300);
+            }
+        }
+
+        let mut output = Output {
+            text: self.format.render(&vars),
+            tooltip: self.tooltip_format.render_optional(&vars),
+            class: vec![self.class.clone()],
+            percentage: Some(perc.as_u8().into()),
+            ..Output::default()
+        };
+        self.thresholds.apply(perc, &mut output);
+
+        Ok(output)
+    }
+}
+
+/// Renders a table with the ten second averages for each resource.
+fn table(pressures: &[(Resource, Pressure)]) -> String {
+    pressures
+        .iter()
+        .map(|(resource, pressure)| {
+            format!(
+                "{:<6}  some {:>6.2}%  full {:>6.2}%",
+                resource.label(),
+                pressure.some.avg10,
+                pressure.full.avg10
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use waybar::Loop;
+
+    #[test]
+    fn test_start_without_trigger() {
+        // The kernel never accepts a stall that's longer than the window, so
+        // this can't be registered whether or not PSI is available.
+        let mut psi = Psi {
+            class: "psi".into(),
+            resources: Resource::ALL.to_vec(),
+            kind: Kind::Some,
+            trigger: Some((Duration::from_secs(4), Duration::from_secs(2))),
+            history: History::new(5),
+            style: Style::Sparks,
+            thresholds: Thresholds::new(None, None, 0),
+            format: "{sparkline}".parse().unwrap(),
+            tooltip_format: "{table}".parse().unwrap(),
+        };
+        let l = Loop::on_demand(|_| Ok(Output::default()));
+
+        assert!(psi.start(&l.trigger()).is_ok());
+    }
+
+    #[test]
+    fn test_table() {
+        let pressures = vec![
+            (
+                Resource::Cpu,
+                Pressure::parse("some avg10=3.20 avg60=0.00 avg300=0.00 total=0").unwrap(),
+            ),
+            (
+                Resource::Memory,
+                Pressure::parse(
+                    "some avg10=12.50 avg60=0.00 avg300=0.00 total=0
+full avg10=0.25 avg60=0.00 avg300=0.00 total=0",
+                )
+                .unwrap(),
+            ),
+        ];
+
+        assert_eq!(
+            "CPU     some   3.20%  full   0.00%\nMemory  some  12.50%  full   0.25%",
+            table(&pressures)
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::prelude::AsRawFd;
+use std::time::Duration;
+
+use crate::pressure::{Kind, Resource};
+
+/// Monitor is a PSI trigger, as described in the kernel's
+/// Documentation/accounting/psi.rst: once it's registered, the kernel wakes
+/// us up with POLLPRI whenever tasks are stalled for longer than the stall
+/// time within a window.
+///
+/// The trigger only lasts as long as the file is open, so the file has to be
+/// kept around even though we never touch it again.
+pub(crate) struct Monitor {
+    resource: Resource,
+    fds: Vec<PollFd>,
+    _file: File,
+}
+
+impl Monitor {
+    pub(crate) fn new(
+        resource: Resource,
+        kind: Kind,
+        stall: Duration,
+        window: Duration,
+    ) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(OFlag::O_NONBLOCK.bits())
+            .open(resource.path())?;
+
+        // The kernel expects the trigger to be NUL terminated.
+        file.write_all(
+            format!(
+                "{} {} {}\0",
+                kind.name(),
+                stall.as_micros(),
+                window.as_micros()
+            )
+            .as_bytes(),
+        )
+        .with_context(|| {
+            format!(
+                "cannot create a PSI trigger for {} (unprivileged triggers require a window that's a multiple of 2s)",
+                resource.name()
+            )
+        })?;
+
+        let fds = vec![PollFd::new(file.as_raw_fd(), PollFlags::POLLPRI)];
+
+        Ok(Self {
+            resource,
+            fds,
+            _file: file,
+        })
+    }
+
+    /// Blocks until the trigger fires.
+    pub(crate) fn block_until_event(&mut self) -> Result<()> {
+        loop {
+            match poll(self.fds.as_mut_slice(), -1) {
+                Ok(_) => {}
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            let revents = self.fds[0].revents().unwrap_or_else(PollFlags::empty);
+            if revents.contains(PollFlags::POLLERR) {
+                return Err(anyhow!(
+                    "PSI trigger for {} is no longer available",
+                    self.resource.name()
+                ));
+            }
+            if revents.contains(PollFlags::POLLPRI) {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{anyhow, Error, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::{FromStr, SplitWhitespace};
+
+/// Resource is one of the resources the kernel reports pressure for.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Resource {
+    Cpu,
+    Memory,
+    Io,
+}
+
+impl Resource {
+    pub(crate) const ALL: [Resource; 3] = [Resource::Cpu, Resource::Memory, Resource::Io];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Resource::Cpu => "cpu",
+            Resource::Memory => "memory",
+            Resource::Io => "io",
+        }
+    }
+
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Resource::Cpu => "CPU",
+            Resource::Memory => "Memory",
+            Resource::Io => "IO",
+        }
+    }
+
+    pub(crate) fn path(&self) -> PathBuf {
+        Path::new("/proc/pressure").join(self.name())
+    }
+}
+
+impl FromStr for Resource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Resource::ALL
+            .iter()
+            .find(|resource| resource.name() == s)
+            .copied()
+            .ok_or_else(|| anyhow!("unknown resource {}: expected cpu, memory, or io", s))
+    }
+}
+
+/// Kind is whether some tasks, or all non-idle tasks, were stalled.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Kind {
+    Some,
+    Full,
+}
+
+impl Kind {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Kind::Some => "some",
+            Kind::Full => "full",
+        }
+    }
+}
+
+/// Averages are the percentages of time that tasks were stalled over the last
+/// 10, 60, and 300 seconds.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Averages {
+    pub(crate) avg10: f64,
+    pub(crate) avg60: f64,
+    pub(crate) avg300: f64,
+}
+
+/// Pressure is the content of a single /proc/pressure file.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Pressure {
+    pub(crate) some: Averages,
+    pub(crate) full: Averages,
+}
+
+impl Pressure {
+    pub(crate) fn read(resource: Resource) -> Result<Self> {
+        Self::parse(&fs::read_to_string(resource.path())?)
+    }
+
+    /// Parses a pressure file. Older kernels don't report full pressure for
+    /// CPUs, in which case it's zero.
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let mut some = None;
+        let mut full = Averages::default();
+
+        for line in s.lines() {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("some") => some = Some(Averages::parse(fields)?),
+                Some("full") => full = Averages::parse(fields)?,
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            some: some.ok_or_else(|| anyhow!("no some line in pressure file"))?,
+            full,
+        })
+    }
+
+    pub(crate) fn get(&self, kind: Kind) -> &Averages {
+        match kind {
+            Kind::Some => &self.some,
+            Kind::Full => &self.full,
+        }
+    }
+}
+
+impl Averages {
+    fn parse(fields: SplitWhitespace) -> Result<Self> {
+        let mut averages = Self::default();
+
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| anyhow!("malformed pressure field {:?}", field))?;
+
+            match key {
+                "avg10" => averages.avg10 = value.parse()?,
+                "avg60" => averages.avg60 = value.parse()?,
+                "avg300" => averages.avg300 = value.parse()?,
+                _ => {}
+            }
+        }
+
+        Ok(averages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let pressure = Pressure::parse(
+            "some avg10=3.20 avg60=5.02 avg300=3.71 total=68436709
+full avg10=0.50 avg60=0.02 avg300=0.05 total=4601070
+",
+        )
+        .unwrap();
+
+        assert_eq!(3.2, pressure.get(Kind::Some).avg10);
+        assert_eq!(3.71, pressure.get(Kind::Some).avg300);
+        assert_eq!(0.5, pressure.get(Kind::Full).avg10);
+
+        let pressure = Pressure::parse("some avg10=1.00 avg60=2.00 avg300=3.00 total=4").unwrap();
+        assert_eq!(Averages::default(), pressure.full);
+
+        assert!(Pressure::parse("").is_err());
+        assert!(Pressure::parse("some avg10").is_err());
+        assert!(Pressure::parse("some avg10=x").is_err());
+    }
+
+    #[test]
+    fn test_resource() {
+        assert_eq!(Resource::Memory, "memory".parse().unwrap());
+        assert!("disk".parse::<Resource>().is_err());
+        assert_eq!(Path::new("/proc/pressure/io"), Resource::Io.path());
+    }
+}
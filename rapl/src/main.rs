@@ -0,0 +1,300 @@
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+use std::time::Instant;
+use structopt::StructOpt;
+use waybar::{CommonOpt, Event, History, Output, Percentage, Template, Vars};
+
+mod zone;
+use zone::{Domain, Zone};
+
+#[cfg(test)]
+mod testing;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(short, long, default_value = "rapl", help = "CSS class")]
+    class: String,
+
+    #[structopt(flatten)]
+    common: CommonOpt,
+
+    #[structopt(
+        short,
+        long,
+        default_value = "5",
+        name = "N",
+        help = "enable sparkline with N historical values"
+    )]
+    sparkline: u8,
+
+    #[structopt(
+        long,
+        default_value = "package",
+        help = "what to report: package, core, uncore, dram, or psys"
+    )]
+    domain: Domain,
+
+    #[structopt(
+        long,
+        name = "WATTS",
+        help = "power that counts as 100% [default: the domain's long term power limit, or the most seen so far]"
+    )]
+    max_power: Option<f64>,
+
+    #[structopt(
+        long,
+        default_value = "/sys/class/powercap",
+        help = "base path to the powercap sysfs"
+    )]
+    sysfs_powercap_path: PathBuf,
+
+    #[structopt(
+        long,
+        default_value = "{sparkline}",
+        help = "format for the module text"
+    )]
+    format: Template,
+
+    #[structopt(
+        long,
+        default_value = "{watts:.1} W\n\n{table}",
+        help = "format for the tooltip"
+    )]
+    tooltip_format: Template,
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    let classes = vec![opt.class];
+
+    let zones = Zone::discover(&opt.sysfs_powercap_path)?;
+    if zones.is_empty() {
+        let mut output = Output::unsupported(&format!(
+            "No CPU power information is available: there are no RAPL zones in {}",
+            opt.sysfs_powercap_path.display()
+        ));
+        output.class.splice(0..0, classes);
+
+        return opt.common.build(move |_| Ok(output.clone()))?.run();
+    }
+
+    let domain = opt.domain;
+    if !zones.iter().any(|zone| zone.domain() == domain) {
+        return Err(anyhow!(
+            "no {} zones in {}",
+            domain.name(),
+            opt.sysfs_powercap_path.display()
+        ));
+    }
+
+    // Set up the history. We'll constrain the sparkline to a maximum of 10
+    // historical values.
+    let mut history = History::new(opt.sparkline.min(10) as usize);
+    let style = opt.common.style;
+    let format = opt.format;
+    let tooltip_format = opt.tooltip_format;
+    let mut thresholds = opt.common.thresholds();
+    let mut gaps = opt.common.gaps();
+    let mut max_power = match opt.max_power {
+        Some(watts) => Some(watts),
+        None => power_limit(&zones, domain)?,
+    };
+    let fixed_max = max_power.is_some();
+    let mut last: Option<Sample> = None;
+    let mut shown: Option<(Percentage, Vars)> = None;
+
+    opt.common
+        .build(|event| {
+            // Refreshes report the power since the last update, but don't move
+            // the baseline or add to the history, so both still follow the
+            // interval.
+            let refresh = event == Event::Refresh;
+            let gap = if refresh {
+                gaps.pending()
+            } else {
+                gaps.sample()
+            };
+            let current = Sample::read(&zones)?;
+
+            // If the system was suspended, the energy used while asleep would
+            // be averaged over the whole gap, so we'll start again from here.
+            if last.is_some() && gap && !refresh {
+                history.push_gap();
+                last = None;
+            }
+
+            if let Some(last) = last.as_ref().filter(|_| !gap) {
+                let power = current.power(last, &zones);
+                let watts = sum(&power, domain);
+
+                // Without a limit, the busiest we've seen has to do.
+                if !fixed_max {
+                    max_power = Some(max_power.unwrap_or_default().max(watts));
+                }
+                // A fixed maximum can be exceeded, at least briefly.
+                let perc = match max_power {
+                    Some(max) => Percentage::calculate(watts.min(max), max),
+                    None => Percentage::default(),
+                };
+
+                if !refresh {
+                    history.push(perc);
+                }
+
+                let vars = Domain::ALL
+                    .iter()
+                    .filter(|domain| power.iter().any(|(zone, _)| zone.domain() == **domain))
+                    .fold(Vars::new(), |vars, domain| {
+                        vars.with(domain.name(), sum(&power, *domain))
+                    })
+                    .with("watts", watts)
+                    .with("max_power", max_power.unwrap_or_default())
+                    .with("percentage", perc)
+                    .with("table", table(&power));
+                shown = Some((perc, vars));
+            }
+
+            // After a gap, there's nothing new to show until the next update,
+            // so we'll carry on showing the last values, but with the gap in
+            // the sparkline.
+            let mut output = Output {
+                class: classes.clone(),
+                ..Output::default()
+            };
+            if let Some((perc, vars)) = &shown {
+                let vars = vars
+                    .clone()
+                    .with("sparkline", history.render(&style, |p| p.as_u8()));
+
+                output.percentage = Some(perc.as_u8().into());
+                output.tooltip = tooltip_format.render_optional(&vars);
+                output.text = format.render(&vars);
+                thresholds.apply(*perc, &mut output);
+            }
+
+            if !refresh {
+                last = Some(current);
+            }
+            Ok(output)
+        })?
+        .run()?;
+
+    Ok(())
+}
+
+/// Sample is the energy counter of each zone at a point in time.
+#[derive(Debug)]
+struct Sample {
+    energy: Vec<u64>,
+    at: Instant,
+}
+
+impl Sample {
+    fn read(zones: &[Zone]) -> Result<Self> {
+        Ok(Self {
+            energy: zones
+                .iter()
+                .map(|zone| zone.energy())
+                .collect::<Result<Vec<u64>>>()?,
+            at: Instant::now(),
+        })
+    }
+
+    /// Calculates the average power of each zone since the last sample, in
+    /// watts.
+    fn power<'a>(&self, last: &Sample, zones: &'a [Zone]) -> Vec<(&'a Zone, f64)> {
+        let elapsed = self.at.duration_since(last.at).as_micros() as f64;
+
+        zones
+            .iter()
+            .zip(self.energy.iter().zip(last.energy.iter()))
+            .map(|(zone, (current, last))| {
+                let energy = zone.energy_since(*last, *current) as f64;
+                (zone, if elapsed > 0. { energy / elapsed } else { 0. })
+            })
+            .collect()
+    }
+}
+
+/// Returns the total power limit of the zones in the domain, in watts, if
+/// they all have one.
+fn power_limit(zones: &[Zone], domain: Domain) -> Result<Option<f64>> {
+    let mut total = 0;
+
+    for zone in zones.iter().filter(|zone| zone.domain() == domain) {
+        match zone.power_limit()? {
+            Some(limit) => total += limit,
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(total as f64 / 1_000_000.))
+}
+
+/// Adds up the power of every zone in the domain, such as each package on a
+/// multi-socket system.
+fn sum(power: &[(&Zone, f64)], domain: Domain) -> f64 {
+    power
+        .iter()
+        .filter(|(zone, _)| zone.domain() == domain)
+        .map(|(_, watts)| watts)
+        .sum()
+}
+
+/// Renders a table with the power of each zone.
+fn table(power: &[(&Zone, f64)]) -> String {
+    let width = power
+        .iter()
+        .map(|(zone, _)| zone.name().len())
+        .max()
+        .unwrap_or_default();
+
+    power
+        .iter()
+        .map(|(zone, watts)| format!("{:<width$} {:>6.2} W", zone.name(), watts, width = width))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::time::Duration;
+    use waybar::testing::Tree;
+
+    #[test]
+    fn test_power() {
+        let root = Tree::new("rapl-power");
+
+        testing::zone(&root, "intel-rapl:0", "package-0", 0, 10_000_000);
+        testing::zone(&root, "intel-rapl:1", "package-1", 0, 10_000_000);
+        testing::zone(&root, "intel-rapl:0:0", "core", 0, 10_000_000);
+        root.write("intel-rapl:0/constraint_0_power_limit_uw", "15000000\n");
+        root.write("intel-rapl:1/constraint_0_power_limit_uw", "10000000\n");
+
+        let zones = Zone::discover(root.path()).unwrap();
+        assert_eq!(Some(25.), power_limit(&zones, Domain::Package).unwrap());
+        assert_eq!(None, power_limit(&zones, Domain::Core).unwrap());
+
+        let now = Instant::now();
+        let last = Sample {
+            energy: vec![9_000_000, 0, 0],
+            at: now,
+        };
+        let current = Sample {
+            energy: vec![1_000_000, 500_000, 4_000_000],
+            at: now + Duration::from_secs(2),
+        };
+
+        // The first package's counter wrapped around.
+        let power = current.power(&last, &zones);
+        assert_eq!(0.25, sum(&power, Domain::Core));
+        assert_eq!(3., sum(&power, Domain::Package));
+        assert_eq!(0., sum(&power, Domain::Dram));
+        assert_eq!(
+            "package-0   1.00 W\ncore        0.25 W\npackage-1   2.00 W",
+            table(&power)
+        );
+    }
+}
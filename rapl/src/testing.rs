@@ -0,0 +1,11 @@
+use waybar::testing::Tree;
+
+/// Writes a RAPL zone into a fake powercap tree.
+pub(crate) fn zone(root: &Tree, dir: &str, name: &str, energy: u64, max_energy: u64) {
+    root.write(format!("{}/name", dir), &format!("{}\n", name));
+    root.write(format!("{}/energy_uj", dir), &format!("{}\n", energy));
+    root.write(
+        format!("{}/max_energy_range_uj", dir),
+        &format!("{}\n", max_energy),
+    );
+}
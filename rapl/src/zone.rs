@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Context, Error, Result};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Domain is the part of the system that a RAPL zone measures.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Domain {
+    Package,
+    Core,
+    Uncore,
+    Dram,
+    Psys,
+}
+
+impl Domain {
+    pub(crate) const ALL: [Domain; 5] = [
+        Domain::Package,
+        Domain::Core,
+        Domain::Uncore,
+        Domain::Dram,
+        Domain::Psys,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Domain::Package => "package",
+            Domain::Core => "core",
+            Domain::Uncore => "uncore",
+            Domain::Dram => "dram",
+            Domain::Psys => "psys",
+        }
+    }
+
+    /// Works out the domain from a zone's name. Packages are numbered, as in
+    /// `package-0`.
+    fn from_zone_name(name: &str) -> Option<Self> {
+        let name = match name.split_once('-') {
+            Some((prefix, n)) if n.parse::<usize>().is_ok() => prefix,
+            _ => name,
+        };
+
+        Domain::ALL
+            .iter()
+            .find(|domain| domain.name() == name)
+            .copied()
+    }
+}
+
+impl FromStr for Domain {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Domain::ALL
+            .iter()
+            .find(|domain| domain.name() == s)
+            .copied()
+            .ok_or_else(|| {
+                anyhow!(
+                    "unknown domain {}: expected package, core, uncore, dram, or psys",
+                    s
+                )
+            })
+    }
+}
+
+/// Zone is a single RAPL powercap zone, such as a CPU package, or the cores
+/// within it. AMD CPUs use the same driver, and so the same names, as Intel
+/// CPUs.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Zone {
+    path: PathBuf,
+    name: String,
+    domain: Domain,
+    max_energy: u64,
+}
+
+impl Zone {
+    /// Finds the RAPL zones under the powercap sysfs path.
+    ///
+    /// Newer Intel CPUs also expose their package through MMIO as
+    /// `intel-rapl-mmio`, which would count the package twice, so that's
+    /// skipped, as are zones for domains we don't know about.
+    pub(crate) fn discover(path: &Path) -> Result<Vec<Self>> {
+        let dir = match path.read_dir() {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut zones = Vec::new();
+        for entry in dir {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if !file_name.contains("rapl:") || file_name.starts_with("intel-rapl-mmio") {
+                continue;
+            }
+
+            let path = entry.path();
+            if !path.join("energy_uj").exists() {
+                continue;
+            }
+
+            let name = fs::read_to_string(path.join("name"))?.trim().to_string();
+            if let Some(domain) = Domain::from_zone_name(&name) {
+                zones.push(Self {
+                    max_energy: read_u64(&path.join("max_energy_range_uj"))?,
+                    path,
+                    name,
+                    domain,
+                });
+            }
+        }
+
+        // Sorting by path keeps each package's subzones after it.
+        zones.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(zones)
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn domain(&self) -> Domain {
+        self.domain
+    }
+
+    /// Returns the energy counter, in microjoules.
+    pub(crate) fn energy(&self) -> Result<u64> {
+        let path = self.path.join("energy_uj");
+
+        read_u64(&path).with_context(|| {
+            format!(
+                "cannot read {} (energy counters are only readable by root by default)",
+                path.display()
+            )
+        })
+    }
+
+    /// Returns the energy used between two readings of the counter, allowing
+    /// for it wrapping around.
+    pub(crate) fn energy_since(&self, last: u64, current: u64) -> u64 {
+        if current >= last {
+            current - last
+        } else {
+            (self.max_energy - last.min(self.max_energy)) + current
+        }
+    }
+
+    /// Returns the long term power limit, in microwatts, if the zone has one.
+    pub(crate) fn power_limit(&self) -> Result<Option<u64>> {
+        match read_u64(&self.path.join("constraint_0_power_limit_uw")) {
+            Ok(limit) if limit > 0 => Ok(Some(limit)),
+            Ok(_) => Ok(None),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn read_u64(path: &Path) -> Result<u64> {
+    Ok(fs::read_to_string(path)?.trim().parse()?)
+}
+
+fn is_not_found(e: &Error) -> bool {
+    matches!(e.downcast_ref::<std::io::Error>(), Some(e) if e.kind() == ErrorKind::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use waybar::testing::Tree;
+
+    #[test]
+    fn test_domain() {
+        assert_eq!(Some(Domain::Package), Domain::from_zone_name("package-1"));
+        assert_eq!(Some(Domain::Dram), Domain::from_zone_name("dram"));
+        assert_eq!(None, Domain::from_zone_name("gpu"));
+        assert_eq!(Domain::Uncore, "uncore".parse().unwrap());
+        assert!("package-0".parse::<Domain>().is_err());
+    }
+
+    #[test]
+    fn test_discover() {
+        let root = Tree::new("rapl-zone");
+
+        assert!(Zone::discover(root.path()).unwrap().is_empty());
+
+        testing::zone(&root, "intel-rapl:0", "package-0", 900, 1000);
+        testing::zone(&root, "intel-rapl:0:0", "core", 500, 1000);
+        testing::zone(&root, "intel-rapl:0:1", "gpu", 0, 1000);
+        testing::zone(&root, "intel-rapl-mmio:0", "package-0", 0, 1000);
+        testing::zone(&root, "intel-rapl:1", "psys", 0, 1000);
+        root.write("intel-rapl:0/constraint_0_power_limit_uw", "15000000\n");
+
+        let zones = Zone::discover(root.path()).unwrap();
+        assert_eq!(
+            vec!["package-0", "core", "psys"],
+            zones.iter().map(|zone| zone.name()).collect::<Vec<_>>()
+        );
+        assert_eq!(Domain::Core, zones[1].domain());
+        assert_eq!(900, zones[0].energy().unwrap());
+        assert_eq!(Some(15_000_000), zones[0].power_limit().unwrap());
+        assert_eq!(None, zones[1].power_limit().unwrap());
+
+        // The counter wraps around after max_energy_range_uj.
+        assert_eq!(100, zones[0].energy_since(900, 1000));
+        assert_eq!(150, zones[0].energy_since(900, 50));
+    }
+}
@@ -13,6 +13,9 @@ use dbus::{
 };
 use thiserror::Error;
 
+// This is generated code, so we won't hold it to the same standard as the rest
+// of the crate.
+#[allow(clippy::all, dead_code)]
 mod raw {
     include!(concat!(env!("OUT_DIR"), "/swaync.rs"));
 }
@@ -104,7 +107,7 @@ impl Client {
         Ok(self.proxy().toggle_visibility()?)
     }
 
-    fn proxy(&self) -> Proxy<&SyncConnection> {
+    fn proxy(&self) -> Proxy<'_, &SyncConnection> {
         self.conn.with_proxy(&self.dest, &self.path, self.timeout)
     }
 }
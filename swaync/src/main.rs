@@ -1,70 +1,117 @@
-use std::sync::mpsc;
-
 use anyhow::Result;
 use num_format::{SystemLocale, ToFormattedString};
 use structopt::StructOpt;
 use swaync_client::Client;
-use waybar::Output;
+use waybar::{Event, Loop, Module, Output, OutputOpt, Template, Trigger, Vars};
 
 #[derive(Debug, StructOpt)]
 struct Opt {
     #[structopt(short, long, default_value = "swaync", help = "CSS class")]
     class: String,
+
+    #[structopt(
+        long,
+        default_value = "{count}",
+        help = "format for the module text when there are notifications"
+    )]
+    format: Template,
+
+    #[structopt(
+        long,
+        default_value = "",
+        help = "format for the module text when there are no notifications"
+    )]
+    format_empty: Template,
+
+    #[structopt(
+        long,
+        default_value = "{notifications}",
+        help = "format for the tooltip"
+    )]
+    tooltip_format: Template,
+
+    #[structopt(flatten)]
+    output: OutputOpt,
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
-    let locale = SystemLocale::default()?;
-    let client = Client::new()?;
 
-    output(
-        &opt.class,
-        &locale,
-        client.notification_count()?,
-        client.get_dnd()?,
-    );
+    opt.output
+        .apply(Loop::on_demand(Notifications {
+            class: opt.class,
+            format: opt.format,
+            format_empty: opt.format_empty,
+            tooltip_format: opt.tooltip_format,
+            locale: SystemLocale::default()?,
+            client: Client::new()?,
+        }))?
+        .run()
+}
 
-    client.subscribe(move |state| {
-        output(&opt.class, &locale, state.count, state.dnd);
-        true
-    })?;
+struct Notifications {
+    class: String,
+    format: Template,
+    format_empty: Template,
+    tooltip_format: Template,
+    locale: SystemLocale,
+    client: Client,
+}
 
-    let (tx, rx) = mpsc::channel();
-    ctrlc::set_handler(move || {
-        tx.send(()).unwrap();
-    })?;
+impl Module for Notifications {
+    fn start(&mut self, trigger: &Trigger) -> Result<()> {
+        // The subscription hands us the new state, but it's simpler to just
+        // treat it as a notification and let update() query swaync directly.
+        let trigger = trigger.clone();
+        self.client
+            .subscribe(move |_state| trigger.refresh().is_ok())?;
 
-    Ok(rx.recv()?)
+        Ok(())
+    }
+
+    fn update(&mut self, _event: Event) -> Result<Output> {
+        Ok(self.output(self.client.notification_count()?, self.client.get_dnd()?))
+    }
 }
 
-fn output(class: &str, locale: &SystemLocale, count: u32, dnd: bool) {
-    let classes = vec![
-        class,
-        if dnd { "dnd" } else { "disturb" },
-        if count == 0 { "empty" } else { "has" },
-    ]
-    .into_iter()
-    .map(String::from)
-    .collect();
+impl Notifications {
+    fn output(&self, count: u32, dnd: bool) -> Output {
+        let classes = vec![
+            self.class.as_str(),
+            if dnd { "dnd" } else { "disturb" },
+            if count == 0 { "empty" } else { "has" },
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+        let vars = Vars::new().with("count", count).with(
+            "notifications",
+            match count {
+                0 => "No notifications".into(),
+                1 => "1 notification".into(),
+                n => format!("{} notifications", n.to_formatted_string(&self.locale)),
+            },
+        );
 
-    if count == 0 {
-        Output {
-            text: "".into(),
-            tooltip: "No notifications".into(),
-            class: classes,
-            percentage: 0,
-        }
-    } else {
         Output {
-            text: format!("{}", count),
-            tooltip: format!(
-                "{} notification{}",
-                count.to_formatted_string(locale),
-                if count != 1 { "s" } else { "" }
+            text: if count == 0 {
+                self.format_empty.render(&vars)
+            } else {
+                self.format.render(&vars)
+            },
+            alt: Some(
+                match (dnd, count) {
+                    (true, 0) => "dnd",
+                    (true, _) => "dnd-has-notifications",
+                    (false, 0) => "no-notifications",
+                    (false, _) => "has-notifications",
+                }
+                .into(),
             ),
+            tooltip: self.tooltip_format.render_optional(&vars),
             class: classes,
-            percentage: 100,
+            percentage: Some(if count == 0 { 0 } else { 100 }),
         }
     }
-    .send();
 }
@@ -0,0 +1,243 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::topology::{optional, parse_cpu_list};
+
+/// Cgroup is a cgroup v2 directory, for modules that need to account for a
+/// container or systemd slice rather than the whole machine.
+///
+/// Limits are inherited, so where a limit is read, the tightest limit of the
+/// cgroup and its ancestors applies.
+#[derive(Debug, Clone)]
+pub struct Cgroup {
+    mount: PathBuf,
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Returns the cgroup that this process belongs to.
+    pub fn own() -> Result<Self> {
+        Self::new(own_path(&fs::read_to_string("/proc/self/cgroup")?)?)
+    }
+
+    /// Returns the cgroup at the given path, which can either be a path within
+    /// the cgroup hierarchy (such as `/user.slice`), or the path to its
+    /// directory in the cgroup filesystem.
+    pub fn new<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mount = mount_point(&fs::read_to_string("/proc/self/mounts")?);
+        let path = path.as_ref();
+        let path = if path.starts_with(&mount) {
+            path.to_path_buf()
+        } else {
+            // Normalising the components drops the trailing slash that
+            // joining the root cgroup would otherwise leave.
+            mount
+                .join(path.strip_prefix("/").unwrap_or(path))
+                .components()
+                .collect()
+        };
+
+        if !path.join("cgroup.controllers").exists() {
+            return Err(anyhow!("{} is not a cgroup v2 directory", path.display()));
+        }
+
+        Ok(Self { mount, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the total CPU time used by the cgroup, in microseconds.
+    pub fn cpu_usage(&self) -> Result<u64> {
+        let stat = self.read("cpu.stat")?;
+
+        stat.lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))
+            .ok_or_else(|| anyhow!("no usage_usec in {}", self.path.join("cpu.stat").display()))?
+            .trim()
+            .parse()
+            .context("malformed usage_usec in cpu.stat")
+    }
+
+    /// Returns the number of CPUs the cgroup can use: its quota, if it has
+    /// one, and otherwise the number of CPUs in its cpuset.
+    pub fn cpus(&self) -> Result<f64> {
+        let mut quota: Option<f64> = None;
+        for dir in self.ancestors() {
+            if let Some(max) = optional(&dir.join("cpu.max"))? {
+                if let Some(cpus) = parse_cpu_max(&max)? {
+                    quota = Some(quota.map_or(cpus, |quota| quota.min(cpus)));
+                }
+            }
+        }
+        if let Some(quota) = quota {
+            return Ok(quota);
+        }
+
+        let cpuset = match optional(&self.path.join("cpuset.cpus.effective"))? {
+            Some(cpuset) if !cpuset.trim().is_empty() => cpuset,
+            _ => fs::read_to_string("/sys/devices/system/cpu/online")?,
+        };
+        Ok(parse_cpu_list(&cpuset)?.len() as f64)
+    }
+
+    /// Returns the memory used by the cgroup in bytes, not including inactive
+    /// file backed memory that the kernel can reclaim, in the same way as
+    /// `docker stats`.
+    pub fn memory_used(&self) -> Result<u64> {
+        let current: u64 = self
+            .read("memory.current")?
+            .trim()
+            .parse()
+            .context("malformed memory.current")?;
+
+        let inactive = optional(&self.path.join("memory.stat"))?
+            .and_then(|stat| {
+                stat.lines()
+                    .find_map(|line| line.strip_prefix("inactive_file "))
+                    .and_then(|value| value.trim().parse::<u64>().ok())
+            })
+            .unwrap_or_default();
+
+        Ok(current.saturating_sub(inactive))
+    }
+
+    /// Returns the memory limit of the cgroup in bytes, if it has one.
+    pub fn memory_max(&self) -> Result<Option<u64>> {
+        let mut limit: Option<u64> = None;
+        for dir in self.ancestors() {
+            if let Some(max) = optional(&dir.join("memory.max"))? {
+                if let Some(max) = parse_max(&max)? {
+                    limit = Some(limit.map_or(max, |limit| limit.min(max)));
+                }
+            }
+        }
+
+        Ok(limit)
+    }
+
+    fn read(&self, file: &str) -> Result<String> {
+        let path = self.path.join(file);
+
+        fs::read_to_string(&path).with_context(|| {
+            format!(
+                "cannot read {} (is the controller enabled for this cgroup?)",
+                path.display()
+            )
+        })
+    }
+
+    fn ancestors(&self) -> impl Iterator<Item = &Path> {
+        let mount = self.mount.as_path();
+
+        self.path
+            .ancestors()
+            .take_while(move |dir| dir.starts_with(mount))
+    }
+}
+
+/// Finds the cgroup v2 entry in /proc/self/cgroup, which looks like
+/// `0::/user.slice/user-1000.slice/session-1.scope`.
+fn own_path(cgroup: &str) -> Result<PathBuf> {
+    cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("this process isn't in a cgroup v2 hierarchy"))
+}
+
+/// Finds where the cgroup v2 filesystem is mounted. This is usually
+/// /sys/fs/cgroup, but systems with a hybrid hierarchy put it elsewhere.
+fn mount_point(mounts: &str) -> PathBuf {
+    mounts
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .find(|fields| fields.get(2) == Some(&"cgroup2"))
+        .and_then(|fields| fields.get(1).map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("/sys/fs/cgroup"))
+}
+
+/// Parses cpu.max, which is either `max PERIOD` or `QUOTA PERIOD`, into a
+/// number of CPUs.
+fn parse_cpu_max(s: &str) -> Result<Option<f64>> {
+    let mut fields = s.split_whitespace();
+    match (fields.next(), fields.next()) {
+        (Some("max"), _) => Ok(None),
+        (Some(quota), Some(period)) => {
+            let period: f64 = period.parse()?;
+            if period == 0. {
+                return Err(anyhow!("invalid period in cpu.max: {:?}", s));
+            }
+
+            Ok(Some(quota.parse::<f64>()? / period))
+        }
+        _ => Err(anyhow!("malformed cpu.max: {:?}", s)),
+    }
+}
+
+/// Parses a limit that's either a number or `max`.
+fn parse_max(s: &str) -> Result<Option<u64>> {
+    match s.trim() {
+        "max" => Ok(None),
+        n => Ok(Some(n.parse()?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Tree;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            PathBuf::from("/user.slice/session-1.scope"),
+            own_path("1:name=systemd:/\n0::/user.slice/session-1.scope\n").unwrap()
+        );
+        assert!(own_path("4:memory:/docker\n").is_err());
+
+        assert_eq!(
+            PathBuf::from("/sys/fs/cgroup/unified"),
+            mount_point("cgroup /sys/fs/cgroup/cpu cgroup rw 0 0\ncgroup2 /sys/fs/cgroup/unified cgroup2 rw 0 0\n")
+        );
+        assert_eq!(PathBuf::from("/sys/fs/cgroup"), mount_point(""));
+
+        assert_eq!(None, parse_cpu_max("max 100000\n").unwrap());
+        assert_eq!(Some(1.5), parse_cpu_max("150000 100000\n").unwrap());
+        assert!(parse_cpu_max("150000").is_err());
+        assert_eq!(None, parse_max("max\n").unwrap());
+        assert_eq!(Some(1024), parse_max("1024\n").unwrap());
+    }
+
+    #[test]
+    fn test_limits() {
+        let mount = Tree::new("cgroup");
+        mount.write("parent/cpu.max", "50000 100000\n");
+        mount.write("parent/memory.max", "1048576\n");
+        mount.write("parent/child/cpu.max", "max 100000\n");
+        mount.write("parent/child/memory.max", "2097152\n");
+        mount.write("parent/child/memory.current", "524288\n");
+        mount.write(
+            "parent/child/memory.stat",
+            "anon 4096\ninactive_file 131072\n",
+        );
+        mount.write(
+            "parent/child/cpu.stat",
+            "usage_usec 12345\nuser_usec 10000\n",
+        );
+
+        let cgroup = Cgroup {
+            mount: mount.path().to_path_buf(),
+            path: mount.join("parent/child"),
+        };
+        assert_eq!(0.5, cgroup.cpus().unwrap());
+        assert_eq!(Some(1048576), cgroup.memory_max().unwrap());
+        assert_eq!(393216, cgroup.memory_used().unwrap());
+        assert_eq!(12345, cgroup.cpu_usage().unwrap());
+    }
+}
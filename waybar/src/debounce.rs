@@ -0,0 +1,53 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::Trigger;
+
+/// Debouncer coalesces noisy notifications (udev is a prime offender) into a
+/// single refresh once the notifications have stopped for the given timeout.
+#[derive(Debug, Clone)]
+pub struct Debouncer {
+    sender: Sender<()>,
+}
+
+impl Debouncer {
+    pub fn new(trigger: Trigger, timeout: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || process(receiver, trigger, timeout));
+
+        Self { sender }
+    }
+
+    pub fn notify(&self) -> Result<()> {
+        Ok(self.sender.send(())?)
+    }
+}
+
+fn process(input: Receiver<()>, trigger: Trigger, timeout: Duration) -> Result<()> {
+    let mut pending = false;
+
+    loop {
+        match input.recv_timeout(timeout) {
+            Ok(()) => {
+                // We received a message, so we should check once the debounce
+                // is complete.
+                pending = true;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                // Nothing left to read on the channel, so let's return.
+                return Ok(());
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                // We hit the debounce timeout, so let's see if we need to do
+                // anything.
+                if pending {
+                    trigger.refresh()?;
+                }
+                pending = false;
+            }
+        }
+    }
+}
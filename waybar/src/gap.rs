@@ -0,0 +1,109 @@
+use libc::{clock_gettime, clockid_t, timespec, CLOCK_BOOTTIME, CLOCK_MONOTONIC};
+use std::time::Duration;
+
+/// How much further CLOCK_BOOTTIME can advance than CLOCK_MONOTONIC between
+/// samples before we decide the system was suspended.
+const SUSPEND_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// Gaps detects when too much time has passed between two samples for a delta
+/// between them to mean anything: either because the system was suspended in
+/// between, which CLOCK_MONOTONIC doesn't count but CLOCK_BOOTTIME does, or
+/// because the gap is simply longer than expected.
+///
+/// Modules that calculate deltas between samples of cumulative counters
+/// should reset their baseline when this detects a gap, rather than reporting
+/// an average over several hours as the current value.
+#[derive(Debug)]
+pub struct Gaps {
+    max: Duration,
+    last: Option<(Duration, Duration)>,
+}
+
+impl Gaps {
+    /// Creates a detector that treats anything longer than max between
+    /// samples as a gap.
+    pub fn new(max: Duration) -> Self {
+        Self { max, last: None }
+    }
+
+    /// Records a sample, and returns true if there was a gap since the last
+    /// one.
+    pub fn sample(&mut self) -> bool {
+        self.check(clock(CLOCK_MONOTONIC), clock(CLOCK_BOOTTIME))
+    }
+
+    /// Returns true if there's been a gap since the last sample, without
+    /// recording a new one. This is for updates that don't move the baseline,
+    /// such as refreshes.
+    pub fn pending(&self) -> bool {
+        self.gap(clock(CLOCK_MONOTONIC), clock(CLOCK_BOOTTIME))
+    }
+
+    fn check(&mut self, monotonic: Duration, boottime: Duration) -> bool {
+        let gap = self.gap(monotonic, boottime);
+        self.last = Some((monotonic, boottime));
+        gap
+    }
+
+    fn gap(&self, monotonic: Duration, boottime: Duration) -> bool {
+        match self.last {
+            Some((last_monotonic, last_boottime)) => {
+                let awake = monotonic.saturating_sub(last_monotonic);
+                let elapsed = boottime.saturating_sub(last_boottime);
+
+                awake > self.max || elapsed.saturating_sub(awake) > SUSPEND_TOLERANCE
+            }
+            None => false,
+        }
+    }
+}
+
+fn clock(id: clockid_t) -> Duration {
+    let mut ts = timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    // Neither clock can fail on Linux, given a valid pointer.
+    unsafe {
+        clock_gettime(id, &mut ts);
+    }
+
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    #[test]
+    fn test_check() {
+        let mut gaps = Gaps::new(secs(10));
+
+        assert!(!gaps.check(secs(100), secs(100)));
+        assert!(!gaps.check(secs(105), secs(105)));
+
+        // Suspended for an hour.
+        assert!(gaps.check(secs(106), secs(3706)));
+        assert!(!gaps.check(secs(107), secs(3707)));
+
+        // Awake, but not sampled for a while.
+        assert!(gaps.gap(secs(130), secs(3730)));
+        assert!(gaps.gap(secs(130), secs(3730)));
+        assert!(gaps.check(secs(130), secs(3730)));
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut gaps = Gaps::new(secs(10));
+
+        assert!(!gaps.pending());
+        assert!(!gaps.sample());
+        assert!(!gaps.sample());
+        assert!(!gaps.pending());
+    }
+}
@@ -1,53 +1,415 @@
-use anyhow::Result;
-use miniserde::{json, Serialize};
-use std::thread::sleep;
+use anyhow::{Error, Result};
+use miniserde::json::{self, Array, Number, Object, Value as JsonValue};
+use std::os::raw::c_int;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
 use std::time::Duration;
 
+mod cgroup;
+pub use cgroup::Cgroup;
+
+mod debounce;
+pub use debounce::Debouncer;
+
+mod gap;
+pub use gap::Gaps;
+
+mod module;
+pub use module::{Event, Module};
+
+mod opt;
+pub use opt::{CommonOpt, OutputOpt};
+
 mod percentage;
 pub use percentage::Percentage;
 
+mod protocol;
+pub use protocol::{Backend, I3bar, Plain, Polybar, Protocol, Waybar};
+
+mod recovery;
+pub use recovery::Recovery;
+
+mod schedule;
+use schedule::Schedule;
+
+mod signal;
+
+mod sink;
+pub use sink::{File, Memory, OutputSink, Stdout, UnixSocket};
+
+mod template;
+pub use template::{Template, Value, Vars};
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+mod threshold;
+pub use threshold::{State, Thresholds};
+
+mod topology;
+pub use topology::{Cluster, Topology};
+
+mod trigger;
+pub use trigger::Trigger;
+
 mod value;
-pub use value::History;
+pub use value::{Blocks, Braille, History, Renderer, Sparks, Style};
 
+/// Loop drives a Module: it updates the module on start, each time the
+/// interval elapses (if there is one), and whenever a Trigger requests a
+/// refresh, sending the output to the bar each time.
+///
+/// Ticks are scheduled against a monotonic deadline, so refreshes and slow
+/// updates don't push back subsequent ticks.
+///
+/// If the module fails to update, the loop sends an error state to the bar and
+/// retries according to its Recovery policy, rather than exiting. Errors from
+/// starting the module are always fatal, since there's no way to know which of
+/// its event sources were set up before it failed.
 #[derive(Debug)]
-pub struct Loop<F> {
-    callback: F,
-    interval: Duration,
+pub struct Loop<M> {
+    module: M,
+    interval: Option<Duration>,
+    align: bool,
+    backend: Box<dyn Backend>,
+    sink: Box<dyn OutputSink>,
+    recovery: Recovery,
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
 }
 
-impl<F> Loop<F>
+impl<M> Loop<M>
 where
-    F: FnMut() -> Result<Output>,
+    M: Module,
 {
-    pub fn new(callback: F, interval: &Duration) -> Self {
+    pub fn new(module: M, interval: &Duration) -> Self {
+        Self::with_interval(module, Some(*interval))
+    }
+
+    /// Creates a loop that only updates the module on start and when
+    /// triggered.
+    pub fn on_demand(module: M) -> Self {
+        Self::with_interval(module, None)
+    }
+
+    fn with_interval(module: M, interval: Option<Duration>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
         Self {
-            callback,
-            interval: interval.clone(),
+            module,
+            interval,
+            align: false,
+            backend: Box::new(Waybar),
+            sink: Box::new(Stdout),
+            recovery: Recovery::default(),
+            sender,
+            receiver,
         }
     }
 
+    /// Aligns ticks to wall clock multiples of the interval, so that modules
+    /// with the same interval update in lockstep.
+    pub fn aligned(mut self, align: bool) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// Sets the backend used to format output. By default, this is Waybar's
+    /// custom module JSON.
+    pub fn backend(mut self, backend: Box<dyn Backend>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Sets the sink that formatted output is sent to. By default, this is
+    /// stdout.
+    pub fn sink(mut self, sink: Box<dyn OutputSink>) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    /// Sets the policy for handling module errors.
+    pub fn recovery(mut self, recovery: Recovery) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Makes any error for which the predicate returns true fatal, on top of
+    /// the rest of the recovery policy.
+    pub fn fatal_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&Error) -> bool + 'static,
+    {
+        self.recovery = std::mem::take(&mut self.recovery).fatal_if(predicate);
+        self
+    }
+
+    pub fn trigger(&self) -> Trigger {
+        Trigger::new(self.sender.clone())
+    }
+
+    /// Refreshes the module each time the process receives the given signal.
+    pub fn refresh_on_signal(&self, signal: c_int) -> Result<()> {
+        signal::forward(signal, self.trigger())
+    }
+
+    /// Refreshes the module each time the process receives SIGRTMIN+offset.
+    pub fn refresh_on_realtime_signal(&self, offset: u8) -> Result<()> {
+        self.refresh_on_signal(signal::realtime(offset)?)
+    }
+
     pub fn run(&mut self) -> Result<()> {
+        let mut schedule = self
+            .interval
+            .map(|interval| Schedule::new(interval, self.align));
+
+        for line in self.backend.header() {
+            self.sink.send(&line)?;
+        }
+
+        self.module.start(&self.trigger())?;
+
+        let mut event = Event::Start;
+        loop {
+            match self.module.update(event) {
+                Ok(output) => {
+                    self.recovery.succeeded();
+                    self.send(&output)?;
+                }
+                Err(err) => {
+                    if !self.recovery.failed(&err) {
+                        return Err(err);
+                    }
+                    self.send(&Output::error(&err))?;
+                }
+            }
+
+            event = self.next_event(&mut schedule)?;
+        }
+    }
+
+    fn next_event(&mut self, schedule: &mut Option<Schedule>) -> Result<Event> {
         loop {
-            (self.callback)()?.send();
-            sleep(self.interval);
+            let timeout = match (schedule.as_ref(), self.recovery.remaining()) {
+                (Some(schedule), Some(retry)) => Some(schedule.remaining().min(retry)),
+                (Some(schedule), None) => Some(schedule.remaining()),
+                (None, retry) => retry,
+            };
+
+            let timeout = match timeout {
+                Some(timeout) => timeout,
+                None => return Ok(self.receiver.recv()?),
+            };
+
+            match self.receiver.recv_timeout(timeout) {
+                Ok(event) => return Ok(event),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            if self.recovery.due() {
+                return Ok(Event::Retry);
+            }
+
+            if let Some(schedule) = schedule {
+                if schedule.remaining() == Duration::ZERO {
+                    schedule.advance();
+
+                    // Ticks are skipped while we're backing off.
+                    if !self.recovery.pending() {
+                        return Ok(Event::Tick);
+                    }
+                }
+            }
         }
     }
+
+    fn send(&mut self, output: &Output) -> Result<()> {
+        let line = self.backend.format(output);
+        self.sink.send(&line)
+    }
 }
 
-#[derive(Debug, Serialize)]
+/// Output is a single update for Waybar's custom module JSON protocol. Optional
+/// fields (and an empty class list) are omitted from the JSON entirely.
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Output {
     pub text: String,
-    pub tooltip: String,
+    pub alt: Option<String>,
+    pub tooltip: Option<String>,
     pub class: Vec<String>,
-    pub percentage: i32,
+    pub percentage: Option<i32>,
 }
 
 impl Output {
-    pub fn send(&self) {
-        println!("{}", self.to_json());
+    /// Returns the output used to report a module error: the text is just
+    /// "error", with the full error in the tooltip.
+    pub fn error(err: &Error) -> Self {
+        Self {
+            text: "error".into(),
+            alt: Some("error".into()),
+            tooltip: Some(format!("{:#}", err)),
+            class: vec!["error".into()],
+            percentage: None,
+        }
+    }
+
+    /// Returns the output used when a module can't work on this system at
+    /// all: the text is just "unsupported", with the reason in the tooltip.
+    pub fn unsupported(reason: &str) -> Self {
+        Self {
+            text: "unsupported".into(),
+            alt: Some("unsupported".into()),
+            tooltip: Some(reason.into()),
+            class: vec!["unsupported".into()],
+            percentage: None,
+        }
     }
 
     pub fn to_json(&self) -> String {
-        json::to_string(self)
+        let mut object = Object::new();
+
+        object.insert("text".into(), JsonValue::String(self.text.clone()));
+        if let Some(alt) = &self.alt {
+            object.insert("alt".into(), JsonValue::String(alt.clone()));
+        }
+        if let Some(tooltip) = &self.tooltip {
+            object.insert("tooltip".into(), JsonValue::String(tooltip.clone()));
+        }
+        if !self.class.is_empty() {
+            object.insert(
+                "class".into(),
+                JsonValue::Array(
+                    self.class
+                        .iter()
+                        .map(|class| JsonValue::String(class.clone()))
+                        .collect::<Array>(),
+                ),
+            );
+        }
+        if let Some(percentage) = self.percentage {
+            object.insert(
+                "percentage".into(),
+                JsonValue::Number(Number::I64(percentage.into())),
+            );
+        }
+
+        json::to_string(&object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_run() {
+        let memory = Memory::new();
+        let mut n = 0;
+        let result = Loop::new(
+            move |_| {
+                n += 1;
+                if n > 3 {
+                    return Err(anyhow!("done"));
+                }
+
+                Ok(Output {
+                    text: n.to_string(),
+                    ..Output::default()
+                })
+            },
+            &Duration::from_millis(1),
+        )
+        .backend(Box::new(I3bar::new("test")))
+        .sink(Box::new(memory.clone()))
+        .recovery(Recovery::default().max_failures(Some(1)))
+        .run();
+
+        assert_eq!("done", result.unwrap_err().to_string());
+        assert_eq!(
+            vec![
+                r#"{"version":1}"#,
+                "[",
+                r#"[{"full_text":"1","name":"test"}]"#,
+                r#",[{"full_text":"2","name":"test"}]"#,
+                r#",[{"full_text":"3","name":"test"}]"#,
+            ],
+            memory.lines()
+        );
+    }
+
+    #[test]
+    fn test_run_recovers() {
+        let memory = Memory::new();
+        let mut n = 0;
+        let result = Loop::new(
+            move |_| {
+                n += 1;
+                match n {
+                    1 | 2 => Err(anyhow!("failed {}", n)),
+                    3 => Ok(Output {
+                        text: "ok".into(),
+                        ..Output::default()
+                    }),
+                    _ => Err(anyhow!("done")),
+                }
+            },
+            &Duration::from_millis(1),
+        )
+        .sink(Box::new(memory.clone()))
+        .recovery(
+            Recovery::new(Duration::from_millis(1), Duration::from_millis(10))
+                .fatal_if(|err| err.to_string() == "done"),
+        )
+        .run();
+
+        assert_eq!("done", result.unwrap_err().to_string());
+        assert_eq!(
+            vec![
+                r#"{"alt":"error","class":["error"],"text":"error","tooltip":"failed 1"}"#,
+                r#"{"alt":"error","class":["error"],"text":"error","tooltip":"failed 2"}"#,
+                r#"{"text":"ok"}"#,
+            ],
+            memory.lines()
+        );
+    }
+
+    #[test]
+    fn test_run_start_fails() {
+        struct Unstartable;
+
+        impl Module for Unstartable {
+            fn start(&mut self, _trigger: &Trigger) -> Result<()> {
+                Err(anyhow!("cannot start"))
+            }
+
+            fn update(&mut self, _event: Event) -> Result<Output> {
+                panic!("updated without starting");
+            }
+        }
+
+        let memory = Memory::new();
+        let result = Loop::on_demand(Unstartable)
+            .sink(Box::new(memory.clone()))
+            .run();
+
+        assert_eq!("cannot start", result.unwrap_err().to_string());
+        assert!(memory.lines().is_empty());
+    }
+
+    #[test]
+    fn test_to_json() {
+        assert_eq!(r#"{"text":""}"#, Output::default().to_json());
+        assert_eq!(
+            r#"{"alt":"dnd","class":["swaync","dnd"],"percentage":0,"text":"3","tooltip":"3 notifications"}"#,
+            Output {
+                text: "3".into(),
+                alt: Some("dnd".into()),
+                tooltip: Some("3 notifications".into()),
+                class: vec!["swaync".into(), "dnd".into()],
+                percentage: Some(0),
+            }
+            .to_json()
+        );
     }
 }
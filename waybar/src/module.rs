@@ -0,0 +1,54 @@
+use anyhow::Result;
+
+use crate::{Output, Trigger};
+
+/// The reason a module is being asked to update.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Event {
+    /// The loop has just started, and this is the first update.
+    Start,
+
+    /// The update interval has elapsed.
+    Tick,
+
+    /// Something outside the loop — a signal, udev, D-Bus, or whatever else
+    /// has a Trigger — has asked for an immediate update.
+    Refresh,
+
+    /// A previous update failed, and the loop is trying again.
+    Retry,
+}
+
+/// Module is implemented by anything that can produce output for Waybar.
+///
+/// Simple polling modules can just use a closure, since there's a blanket
+/// implementation for `FnMut(Event) -> Result<Output>`; modules that need to
+/// listen for external events should implement this trait directly and set up
+/// their event sources in `start()`.
+///
+/// Modules that keep a history or calculate deltas shouldn't sample on a
+/// refresh: they should just report the current values, so that the sparkline
+/// still has one value per interval however often something asks for a
+/// refresh.
+pub trait Module {
+    /// Called once by the loop before the first update, with a trigger that
+    /// can be cloned into any threads or callbacks that need to request a
+    /// refresh.
+    ///
+    /// Errors are fatal: the loop won't retry. Modules that can still work
+    /// without their event sources should fall back to polling instead.
+    fn start(&mut self, _trigger: &Trigger) -> Result<()> {
+        Ok(())
+    }
+
+    fn update(&mut self, event: Event) -> Result<Output>;
+}
+
+impl<F> Module for F
+where
+    F: FnMut(Event) -> Result<Output>,
+{
+    fn update(&mut self, event: Event) -> Result<Output> {
+        self(event)
+    }
+}
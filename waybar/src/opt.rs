@@ -0,0 +1,156 @@
+use anyhow::Result;
+use humantime::Duration;
+use signal_hook::consts::SIGUSR1;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use crate::{
+    File, Gaps, Loop, Module, OutputSink, Protocol, Recovery, Stdout, Style, Thresholds, UnixSocket,
+};
+
+/// Options shared by all of the polling modules. Binaries should include this
+/// in their own options with `#[structopt(flatten)]`.
+#[derive(Debug, StructOpt)]
+pub struct CommonOpt {
+    #[structopt(short, long, default_value = "1s", help = "interval between updates")]
+    pub interval: Duration,
+
+    #[structopt(long, help = "align updates to wall clock multiples of the interval")]
+    pub align: bool,
+
+    #[structopt(
+        long,
+        name = "SIGNAL",
+        help = "also refresh immediately on SIGRTMIN+SIGNAL, as used by Waybar's signal option"
+    )]
+    pub signal: Option<u8>,
+
+    #[structopt(
+        long,
+        default_value = "sparks",
+        help = "sparkline style: sparks, blocks, or braille"
+    )]
+    pub style: Style,
+
+    #[structopt(long, help = "percentage at which to add the warning class")]
+    pub warning: Option<u8>,
+
+    #[structopt(long, help = "percentage at which to add the critical class")]
+    pub critical: Option<u8>,
+
+    #[structopt(
+        long,
+        default_value = "5",
+        help = "percentage points a value must drop below a threshold to leave its state"
+    )]
+    pub hysteresis: u8,
+
+    #[structopt(flatten)]
+    pub output: OutputOpt,
+}
+
+impl CommonOpt {
+    /// Builds a Loop for the given module configured with these options.
+    ///
+    /// The loop will always refresh immediately on SIGUSR1, in addition to
+    /// any realtime signal that was configured.
+    pub fn build<M>(&self, module: M) -> Result<Loop<M>>
+    where
+        M: Module,
+    {
+        let l = self
+            .output
+            .apply(Loop::new(module, &self.interval).aligned(self.align))?;
+
+        l.refresh_on_signal(SIGUSR1)?;
+        if let Some(offset) = self.signal {
+            l.refresh_on_realtime_signal(offset)?;
+        }
+
+        Ok(l)
+    }
+
+    pub fn thresholds(&self) -> Thresholds {
+        Thresholds::new(self.warning, self.critical, self.hysteresis)
+    }
+
+    /// Returns a gap detector for modules that calculate deltas. Anything
+    /// longer than ten intervals between samples is treated as a gap, as is
+    /// any suspend.
+    pub fn gaps(&self) -> Gaps {
+        Gaps::new(
+            self.interval
+                .checked_mul(10)
+                .unwrap_or(std::time::Duration::MAX),
+        )
+    }
+}
+
+/// Options controlling how output is sent and how errors are reported,
+/// shared by all of the modules.
+/// Polling modules get these as part of CommonOpt; other modules should
+/// include this with `#[structopt(flatten)]`.
+#[derive(Debug, StructOpt)]
+pub struct OutputOpt {
+    #[structopt(
+        long,
+        default_value = "waybar",
+        help = "output protocol: waybar, i3bar, polybar, or plain"
+    )]
+    pub protocol: Protocol,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with = "output-socket",
+        help = "append output to this file instead of stdout"
+    )]
+    pub output_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "send output to this Unix socket instead of stdout"
+    )]
+    pub output_socket: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "1m",
+        help = "maximum delay between retries after the module fails"
+    )]
+    pub max_backoff: Duration,
+
+    #[structopt(
+        long,
+        help = "exit after this many consecutive failures, instead of retrying forever"
+    )]
+    pub max_failures: Option<u32>,
+}
+
+impl OutputOpt {
+    /// Configures the output of the given Loop with these options.
+    pub fn apply<M>(&self, l: Loop<M>) -> Result<Loop<M>>
+    where
+        M: Module,
+    {
+        Ok(l.backend(self.protocol.backend())
+            .sink(self.sink()?)
+            .recovery(self.recovery()))
+    }
+
+    fn recovery(&self) -> Recovery {
+        let max = *self.max_backoff;
+
+        Recovery::new(max.min(std::time::Duration::from_secs(1)), max)
+            .max_failures(self.max_failures)
+    }
+
+    fn sink(&self) -> Result<Box<dyn OutputSink>> {
+        Ok(match (&self.output_file, &self.output_socket) {
+            (Some(path), _) => Box::new(File::open(path)?),
+            (None, Some(path)) => Box::new(UnixSocket::connect(path)?),
+            (None, None) => Box::new(Stdout),
+        })
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{anyhow, Error};
+use miniserde::json::{self, Array, Object, Value as JsonValue};
+use std::fmt::Debug;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::Output;
+
+const WARNING_COLOUR: &str = "#ffb86c";
+const CRITICAL_COLOUR: &str = "#ff5555";
+
+/// Backend turns Outputs into the lines expected by a particular status bar.
+pub trait Backend: Debug {
+    /// Returns any lines that must be sent before the first output.
+    fn header(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn format(&mut self, output: &Output) -> String;
+}
+
+/// Waybar is the custom module JSON protocol: one JSON object per line.
+#[derive(Debug, Clone, Copy)]
+pub struct Waybar;
+
+impl Backend for Waybar {
+    fn format(&mut self, output: &Output) -> String {
+        output.to_json()
+    }
+}
+
+/// I3bar is the [i3bar
+/// protocol](https://i3wm.org/docs/i3bar-protocol.html), as also used by
+/// swaybar: a header, followed by an infinite JSON array of status lines, each
+/// of which is an array of blocks.
+#[derive(Debug, Clone)]
+pub struct I3bar {
+    name: String,
+    started: bool,
+}
+
+impl I3bar {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            started: false,
+        }
+    }
+}
+
+impl Backend for I3bar {
+    fn header(&mut self) -> Vec<String> {
+        vec![r#"{"version":1}"#.into(), "[".into()]
+    }
+
+    fn format(&mut self, output: &Output) -> String {
+        let mut block = Object::new();
+
+        block.insert("full_text".into(), JsonValue::String(output.text.clone()));
+        block.insert("name".into(), JsonValue::String(self.name.clone()));
+        if let Some(colour) = colour(output) {
+            block.insert("color".into(), JsonValue::String(colour.into()));
+        }
+
+        let line = json::to_string(&std::iter::once(JsonValue::Object(block)).collect::<Array>());
+        if self.started {
+            format!(",{}", line)
+        } else {
+            self.started = true;
+            line
+        }
+    }
+}
+
+/// Polybar is plain text for a Polybar script module with `tail = true`, with
+/// `%{F}` colour tags when the output is in a warning or critical state.
+#[derive(Debug, Clone, Copy)]
+pub struct Polybar;
+
+impl Backend for Polybar {
+    fn format(&mut self, output: &Output) -> String {
+        // Polybar would interpret any formatting tags in the text, so we need to
+        // escape them.
+        let text = output.text.replace("%{", "%%{");
+
+        match colour(output) {
+            Some(colour) => format!("%{{F{}}}{}%{{F-}}", colour, text),
+            None => text,
+        }
+    }
+}
+
+/// Plain is just the text, one line per update.
+#[derive(Debug, Clone, Copy)]
+pub struct Plain;
+
+impl Backend for Plain {
+    fn format(&mut self, output: &Output) -> String {
+        output.text.clone()
+    }
+}
+
+fn colour(output: &Output) -> Option<&'static str> {
+    let has = |class: &str| output.class.iter().any(|c| c == class);
+
+    if has("critical") {
+        Some(CRITICAL_COLOUR)
+    } else if has("warning") {
+        Some(WARNING_COLOUR)
+    } else {
+        None
+    }
+}
+
+/// Protocol selects one of the built in backends, typically from the command
+/// line.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Protocol {
+    Waybar,
+    I3bar,
+    Polybar,
+    Plain,
+}
+
+impl Protocol {
+    pub fn backend(&self) -> Box<dyn Backend> {
+        match self {
+            Protocol::Waybar => Box::new(Waybar),
+            Protocol::I3bar => Box::new(I3bar::new(&program_name())),
+            Protocol::Polybar => Box::new(Polybar),
+            Protocol::Plain => Box::new(Plain),
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waybar" => Ok(Protocol::Waybar),
+            "i3bar" | "swaybar" => Ok(Protocol::I3bar),
+            "polybar" => Ok(Protocol::Polybar),
+            "plain" => Ok(Protocol::Plain),
+            _ => Err(anyhow!(
+                "unknown protocol {}: expected waybar, i3bar, polybar, or plain",
+                s
+            )),
+        }
+    }
+}
+
+fn program_name() -> String {
+    std::env::args_os()
+        .next()
+        .as_deref()
+        .and_then(|arg| Path::new(arg).file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(text: &str, class: &str) -> Output {
+        Output {
+            text: text.into(),
+            class: vec!["cpu".into(), class.into()],
+            ..Output::default()
+        }
+    }
+
+    #[test]
+    fn test_i3bar() {
+        let mut backend = I3bar::new("cpu");
+
+        assert_eq!(vec![r#"{"version":1}"#, "["], backend.header());
+        assert_eq!(
+            r#"[{"full_text":"12%","name":"cpu"}]"#,
+            backend.format(&output("12%", "normal"))
+        );
+        assert_eq!(
+            r##",[{"color":"#ff5555","full_text":"95%","name":"cpu"}]"##,
+            backend.format(&output("95%", "critical"))
+        );
+    }
+
+    #[test]
+    fn test_polybar() {
+        assert_eq!("12%", Polybar.format(&output("12%", "normal")));
+        assert_eq!(
+            "%{F#ffb86c}75%%{F-}",
+            Polybar.format(&output("75%", "warning"))
+        );
+        assert_eq!("%%{F-}", Polybar.format(&output("%{F-}", "normal")));
+    }
+
+    #[test]
+    fn test_plain() {
+        assert_eq!("12%", Plain.format(&output("12%", "critical")));
+    }
+}
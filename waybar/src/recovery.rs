@@ -0,0 +1,196 @@
+use anyhow::Error;
+use std::time::{Duration, Instant};
+use std::{fmt, io};
+
+type Predicate = Box<dyn Fn(&Error) -> bool>;
+
+/// Recovery decides what a Loop does when its module fails to update.
+///
+/// By default, no error is fatal: the loop sends an error state to the bar and
+/// retries with exponential backoff, starting at one second and doubling up to
+/// a minute. Regular ticks are skipped while a retry is pending, although
+/// refreshes still go through immediately.
+///
+/// Errors can be made fatal after a number of consecutive failures with
+/// `max_failures()`, or based on the error itself with `fatal_if()`.
+pub struct Recovery {
+    initial: Duration,
+    max: Duration,
+    max_failures: Option<u32>,
+    fatal: Option<Predicate>,
+    failures: u32,
+    retry: Option<Instant>,
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+impl fmt::Debug for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recovery")
+            .field("initial", &self.initial)
+            .field("max", &self.max)
+            .field("max_failures", &self.max_failures)
+            .field("fatal", &self.fatal.is_some())
+            .field("failures", &self.failures)
+            .field("retry", &self.retry)
+            .finish()
+    }
+}
+
+impl Recovery {
+    /// Creates a policy that backs off from the initial delay, doubling after
+    /// each consecutive failure up to the maximum delay.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            max_failures: None,
+            fatal: None,
+            failures: 0,
+            retry: None,
+        }
+    }
+
+    /// Gives up once there have been this many consecutive failures. A value
+    /// of 1 makes every error fatal.
+    pub fn max_failures(mut self, max_failures: Option<u32>) -> Self {
+        self.max_failures = max_failures;
+        self
+    }
+
+    /// Gives up immediately on any error for which the predicate returns true.
+    pub fn fatal_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&Error) -> bool + 'static,
+    {
+        self.fatal = Some(Box::new(predicate));
+        self
+    }
+
+    /// Returns true if the error was caused by a missing file. This is
+    /// intended for use with `fatal_if()` by modules that read from /proc or
+    /// /sys, where a file that isn't there now isn't going to turn up later.
+    pub fn not_found(err: &Error) -> bool {
+        err.chain().any(|cause| {
+            matches!(cause.downcast_ref::<io::Error>(), Some(e) if e.kind() == io::ErrorKind::NotFound)
+        })
+    }
+
+    /// Returns true if a retry is pending.
+    pub(crate) fn pending(&self) -> bool {
+        self.retry.is_some()
+    }
+
+    /// Returns the time left until the pending retry, if there is one.
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.retry
+            .map(|retry| retry.saturating_duration_since(Instant::now()))
+    }
+
+    /// Returns true if the pending retry is due, and clears it if so.
+    pub(crate) fn due(&mut self) -> bool {
+        match self.retry {
+            Some(retry) if retry <= Instant::now() => {
+                self.retry = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Records a successful update, resetting the backoff.
+    pub(crate) fn succeeded(&mut self) {
+        self.failures = 0;
+        self.retry = None;
+    }
+
+    /// Records a failed update. Returns false if the error is fatal;
+    /// otherwise, schedules a retry and returns true.
+    pub(crate) fn failed(&mut self, err: &Error) -> bool {
+        self.failures = self.failures.saturating_add(1);
+
+        if matches!(self.max_failures, Some(max) if self.failures >= max)
+            || matches!(&self.fatal, Some(fatal) if fatal(err))
+        {
+            return false;
+        }
+
+        self.retry = Some(Instant::now() + self.delay());
+        true
+    }
+
+    fn delay(&self) -> Duration {
+        let factor = 1u32
+            .checked_shl(self.failures.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+
+        self.initial
+            .checked_mul(factor)
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_backoff() {
+        let mut recovery = Recovery::new(Duration::from_secs(1), Duration::from_secs(10));
+        let err = anyhow!("oops");
+
+        let delays: Vec<Duration> = (0..6)
+            .map(|_| {
+                assert!(recovery.failed(&err));
+                recovery.delay()
+            })
+            .collect();
+        assert_eq!(
+            vec![1, 2, 4, 8, 10, 10],
+            delays.iter().map(Duration::as_secs).collect::<Vec<_>>()
+        );
+        assert!(recovery.pending());
+
+        recovery.succeeded();
+        assert!(!recovery.pending());
+        assert!(recovery.failed(&err));
+        assert_eq!(Duration::from_secs(1), recovery.delay());
+
+        for _ in 0..100 {
+            recovery.failed(&err);
+        }
+        assert_eq!(Duration::from_secs(10), recovery.delay());
+    }
+
+    #[test]
+    fn test_fatal() {
+        let mut recovery = Recovery::default().max_failures(Some(3));
+        let err = anyhow!("oops");
+
+        assert!(recovery.failed(&err));
+        assert!(recovery.failed(&err));
+        assert!(!recovery.failed(&err));
+
+        let mut recovery =
+            Recovery::default().fatal_if(|err| err.downcast_ref::<std::io::Error>().is_some());
+
+        assert!(recovery.failed(&err));
+        assert!(!recovery.failed(&std::io::Error::from(std::io::ErrorKind::NotFound).into()));
+    }
+
+    #[test]
+    fn test_not_found() {
+        let err = Error::from(io::Error::from(io::ErrorKind::NotFound)).context("cannot read");
+        assert!(Recovery::not_found(&err));
+
+        assert!(!Recovery::not_found(&anyhow!("oops")));
+        assert!(!Recovery::not_found(
+            &io::Error::from(io::ErrorKind::PermissionDenied).into()
+        ));
+    }
+}
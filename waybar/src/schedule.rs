@@ -0,0 +1,157 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Schedule tracks the deadline for the next tick of a Loop.
+///
+/// Deadlines are calculated from the previous deadline, rather than from
+/// whenever the module finished updating, so the time taken to update doesn't
+/// accumulate into drift. If alignment is enabled, each deadline is also
+/// snapped to the nearest wall clock multiple of the interval, which means
+/// that every module with the same interval ticks at the same moment.
+#[derive(Debug)]
+pub(crate) struct Schedule {
+    interval: Duration,
+    align: bool,
+    deadline: Instant,
+}
+
+impl Schedule {
+    pub(crate) fn new(interval: Duration, align: bool) -> Self {
+        let now = Instant::now();
+        let deadline = if align {
+            now + until_boundary(interval, wall_clock())
+        } else {
+            now + interval
+        };
+
+        Self {
+            interval,
+            align,
+            deadline,
+        }
+    }
+
+    /// Returns the time left until the next tick.
+    pub(crate) fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Moves the deadline on to the next tick.
+    pub(crate) fn advance(&mut self) {
+        self.deadline = self.next_deadline(Instant::now(), wall_clock());
+    }
+
+    fn next_deadline(&self, now: Instant, wall: Duration) -> Instant {
+        let interval = self.interval.as_nanos();
+        if interval == 0 {
+            return now;
+        }
+
+        // If the last update overran, we'll skip the ticks we missed rather
+        // than trying to catch up.
+        let mut next = self.deadline + self.interval;
+        if next <= now {
+            let missed = (now - self.deadline).as_nanos() / interval;
+            next = self.deadline + nanos(interval * (missed + 1));
+        }
+
+        if self.align {
+            // The monotonic and wall clocks can drift apart (thanks, NTP), so
+            // we'll snap to whichever boundary is closest to the deadline.
+            let offset = (wall + (next - now)).as_nanos() % interval;
+            if offset * 2 < interval {
+                next -= nanos(offset);
+            } else {
+                next += nanos(interval - offset);
+            }
+
+            if next <= now {
+                next += self.interval;
+            }
+        }
+
+        next
+    }
+}
+
+fn until_boundary(interval: Duration, wall: Duration) -> Duration {
+    match interval.as_nanos() {
+        0 => Duration::default(),
+        n => nanos(n - wall.as_nanos() % n),
+    }
+}
+
+fn nanos(n: u128) -> Duration {
+    Duration::from_nanos(n as u64)
+}
+
+fn wall_clock() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(interval: u64, align: bool, deadline: Instant) -> Schedule {
+        Schedule {
+            interval: Duration::from_secs(interval),
+            align,
+            deadline,
+        }
+    }
+
+    #[test]
+    fn test_until_boundary() {
+        let five = Duration::from_secs(5);
+
+        assert_eq!(
+            Duration::from_millis(1800),
+            until_boundary(five, Duration::from_millis(123_203_200))
+        );
+        assert_eq!(five, until_boundary(five, Duration::from_secs(10)));
+        assert_eq!(
+            Duration::default(),
+            until_boundary(Duration::default(), Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn test_next_deadline() {
+        let start = Instant::now();
+        let s = schedule(5, false, start);
+
+        // An update that finishes before the next deadline doesn't affect it.
+        assert_eq!(
+            start + Duration::from_secs(5),
+            s.next_deadline(start + Duration::from_secs(2), Duration::default())
+        );
+
+        // An update that overruns skips the missed ticks.
+        assert_eq!(
+            start + Duration::from_secs(15),
+            s.next_deadline(start + Duration::from_secs(12), Duration::default())
+        );
+    }
+
+    #[test]
+    fn test_next_deadline_aligned() {
+        let start = Instant::now();
+        let s = schedule(5, true, start);
+
+        // Wall clock is slightly ahead of the monotonic deadline, so we should
+        // pull the next deadline back.
+        let now = start + Duration::from_millis(10);
+        assert_eq!(
+            start + Duration::from_millis(4900),
+            s.next_deadline(now, Duration::from_millis(100_110))
+        );
+
+        // Wall clock is slightly behind, so we should push it forward.
+        assert_eq!(
+            start + Duration::from_millis(5100),
+            s.next_deadline(now, Duration::from_millis(99_910))
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use std::os::raw::c_int;
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use signal_hook::iterator::Signals;
+
+use crate::Trigger;
+
+/// Spawns a thread that requests a refresh each time the given signal is
+/// received.
+pub(crate) fn forward(signal: c_int, trigger: Trigger) -> Result<()> {
+    let mut signals = Signals::new([signal])?;
+
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if trigger.refresh().is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Returns the signal number for SIGRTMIN+offset, which is how Waybar
+/// describes signals in its configuration.
+pub(crate) fn realtime(offset: u8) -> Result<c_int> {
+    let signal = libc::SIGRTMIN() + c_int::from(offset);
+
+    if signal > libc::SIGRTMAX() {
+        Err(anyhow!(
+            "SIGRTMIN+{} is out of range: the maximum is SIGRTMIN+{}",
+            offset,
+            libc::SIGRTMAX() - libc::SIGRTMIN()
+        ))
+    } else {
+        Ok(signal)
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::Result;
+use std::fmt::Debug;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// OutputSink is where a Loop sends each formatted line of output.
+pub trait OutputSink: Debug {
+    fn send(&mut self, line: &str) -> Result<()>;
+}
+
+/// Stdout writes each line to standard output, which is what Waybar and
+/// friends expect.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stdout;
+
+impl OutputSink for Stdout {
+    fn send(&mut self, line: &str) -> Result<()> {
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+
+        writeln!(handle, "{}", line)?;
+        Ok(handle.flush()?)
+    }
+}
+
+/// Memory retains each line in memory, which is mostly useful for tests. Clones
+/// share the same buffer, so one clone can be given to a Loop while another is
+/// used to inspect the output.
+#[derive(Debug, Clone, Default)]
+pub struct Memory(Arc<Mutex<Vec<String>>>);
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl OutputSink for Memory {
+    fn send(&mut self, line: &str) -> Result<()> {
+        self.0.lock().unwrap().push(line.into());
+        Ok(())
+    }
+}
+
+/// File appends each line to a file, creating it if required.
+#[derive(Debug)]
+pub struct File(std::fs::File);
+
+impl File {
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ))
+    }
+}
+
+impl OutputSink for File {
+    fn send(&mut self, line: &str) -> Result<()> {
+        writeln!(self.0, "{}", line)?;
+        Ok(self.0.flush()?)
+    }
+}
+
+/// UnixSocket writes each line to a stream socket that something else is
+/// already listening on.
+#[derive(Debug)]
+pub struct UnixSocket(UnixStream);
+
+impl UnixSocket {
+    pub fn connect<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self(UnixStream::connect(path)?))
+    }
+}
+
+impl OutputSink for UnixSocket {
+    fn send(&mut self, line: &str) -> Result<()> {
+        writeln!(self.0, "{}", line)?;
+        Ok(self.0.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Tree;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_memory() {
+        let memory = Memory::new();
+        let mut sink = memory.clone();
+
+        sink.send("a").unwrap();
+        sink.send("b").unwrap();
+        assert_eq!(vec!["a", "b"], memory.lines());
+    }
+
+    #[test]
+    fn test_file_and_socket() {
+        let dir = Tree::new("sink");
+
+        let path = dir.join("output");
+        let mut file = File::open(&path).unwrap();
+        file.send("a").unwrap();
+        file.send("b").unwrap();
+        assert_eq!("a\nb\n", std::fs::read_to_string(&path).unwrap());
+
+        let path = dir.join("socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut socket = UnixSocket::connect(&path).unwrap();
+        socket.send("c").unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        assert_eq!("c\n", line);
+    }
+}
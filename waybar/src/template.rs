@@ -0,0 +1,365 @@
+use anyhow::{anyhow, Error, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Percentage;
+
+/// Template is a parsed format string with named placeholders, such as
+/// `{percentage:>3}% {sparkline}`.
+///
+/// Placeholders accept a subset of Rust's format specification after a colon:
+/// `[[fill]align][0][width][.precision]`, where align is one of `<`, `^`, or
+/// `>`. Precision is the number of decimal places for floating point values,
+/// and the maximum length for text, which is truncated if it's longer. Literal
+/// braces can be written as `{{` and `}}`.
+///
+/// Placeholders that don't have a value are rendered verbatim, so typos are
+/// easy to spot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder { name: String, spec: Spec },
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Align {
+    Left,
+    Centre,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Spec {
+    raw: String,
+    fill: char,
+    align: Option<Align>,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+impl Template {
+    pub fn render(&self, vars: &Vars) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(s) => s.clone(),
+                Part::Placeholder { name, spec } => match vars.0.get(name) {
+                    Some(value) => spec.apply(value),
+                    None if spec.raw.is_empty() => format!("{{{}}}", name),
+                    None => format!("{{{}:{}}}", name, spec.raw),
+                },
+            })
+            .collect()
+    }
+
+    /// Renders the template for an optional field, such as the tooltip,
+    /// returning None if it renders as nothing so the field can be left out.
+    pub fn render_optional(&self, vars: &Vars) -> Option<String> {
+        Some(self.render(vars)).filter(|rendered| !rendered.is_empty())
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(anyhow!("unclosed placeholder in {:?}", s)),
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    let (name, spec) = match placeholder.split_once(':') {
+                        Some((name, spec)) => (name, spec),
+                        None => (placeholder.as_str(), ""),
+                    };
+                    if name.is_empty() {
+                        return Err(anyhow!("empty placeholder name in {:?}", s));
+                    }
+
+                    parts.push(Part::Placeholder {
+                        name: name.into(),
+                        spec: spec.parse()?,
+                    });
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+impl FromStr for Spec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let align_of = |c: char| match c {
+            '<' => Some(Align::Left),
+            '^' => Some(Align::Centre),
+            '>' => Some(Align::Right),
+            _ => None,
+        };
+
+        let mut spec = Self {
+            raw: s.into(),
+            fill: ' ',
+            align: None,
+            width: None,
+            precision: None,
+        };
+
+        let chars: Vec<char> = s.chars().collect();
+        let mut rest = &chars[..];
+        if let Some(align) = chars.get(1).copied().and_then(align_of) {
+            spec.fill = chars[0];
+            spec.align = Some(align);
+            rest = &chars[2..];
+        } else if let Some(align) = chars.first().copied().and_then(align_of) {
+            spec.align = Some(align);
+            rest = &chars[1..];
+        }
+
+        if rest.first() == Some(&'0') && spec.align.is_none() {
+            spec.fill = '0';
+            spec.align = Some(Align::Right);
+        }
+
+        let rest: String = rest.iter().collect();
+        let (width, precision) = match rest.split_once('.') {
+            Some((width, precision)) => (width, Some(precision)),
+            None => (rest.as_str(), None),
+        };
+
+        let number = |n: &str| {
+            n.parse::<usize>()
+                .map_err(|_| anyhow!("invalid format specification {:?}", s))
+        };
+        if !width.is_empty() {
+            spec.width = Some(number(width)?);
+        }
+        if let Some(precision) = precision {
+            spec.precision = Some(number(precision)?);
+        }
+
+        Ok(spec)
+    }
+}
+
+impl Spec {
+    fn apply(&self, value: &Value) -> String {
+        let (s, default_align) = match value {
+            Value::Text(s) => (
+                match self.precision {
+                    Some(n) => s.chars().take(n).collect(),
+                    None => s.clone(),
+                },
+                Align::Left,
+            ),
+            Value::Int(n) => (n.to_string(), Align::Right),
+            Value::Float(f) => (
+                format!("{:.*}", self.precision.unwrap_or(1), f),
+                Align::Right,
+            ),
+        };
+
+        let len = s.chars().count();
+        let padding = match self.width {
+            Some(width) if width > len => width - len,
+            _ => return s,
+        };
+
+        let fill = |n: usize| self.fill.to_string().repeat(n);
+        match self.align.unwrap_or(default_align) {
+            Align::Left => format!("{}{}", s, fill(padding)),
+            Align::Right => format!("{}{}", fill(padding), s),
+            Align::Centre => format!("{}{}{}", fill(padding / 2), s, fill(padding - padding / 2)),
+        }
+    }
+}
+
+/// Value is a single value that can be substituted into a Template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Int(i64),
+    Float(f64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Text(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Text(s.into())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Self {
+        Value::Int(n.into())
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Self {
+        Value::Int(n as i64)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Value::Int(n as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Float(n)
+    }
+}
+
+impl From<Percentage> for Value {
+    fn from(p: Percentage) -> Self {
+        Value::Int(p.as_u8().into())
+    }
+}
+
+/// Vars holds the values available to a Template.
+#[derive(Debug, Clone, Default)]
+pub struct Vars(HashMap<String, Value>);
+
+impl Vars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<V>(mut self, name: &str, value: V) -> Self
+    where
+        V: Into<Value>,
+    {
+        self.0.insert(name.into(), value.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, vars: &Vars) -> String {
+        template.parse::<Template>().unwrap().render(vars)
+    }
+
+    #[test]
+    fn test_render() {
+        let vars = Vars::new()
+            .with("percentage", Percentage::from(0.42))
+            .with("sparkline", "{1,2,3}")
+            .with("used", 7.3125);
+
+        assert_eq!("42%", render("{percentage}%", &vars));
+        assert_eq!("{1,2,3} 7.3", render("{sparkline} {used}", &vars));
+        assert_eq!("{ 42 }", render("{{{percentage:^4}}}", &vars));
+        assert_eq!(
+            "{missing} {missing:>3}",
+            render("{missing} {missing:>3}", &vars)
+        );
+    }
+
+    #[test]
+    fn test_render_optional() {
+        let vars = Vars::new().with("status", "").with("count", 3u64);
+
+        assert_eq!(
+            Some("3".to_string()),
+            "{count}"
+                .parse::<Template>()
+                .unwrap()
+                .render_optional(&vars)
+        );
+        assert_eq!(
+            None,
+            "{status}"
+                .parse::<Template>()
+                .unwrap()
+                .render_optional(&vars)
+        );
+        assert_eq!(None, "".parse::<Template>().unwrap().render_optional(&vars));
+    }
+
+    #[test]
+    fn test_spec() {
+        let vars = Vars::new()
+            .with("n", 7u64)
+            .with("f", 1.23456)
+            .with("s", "notifications");
+
+        assert_eq!(
+            "  7|7  |007|**7",
+            render("{n:3}|{n:<3}|{n:03}|{n:*>3}", &vars)
+        );
+        assert_eq!("1.23|  1.2", render("{f:.2}|{f:5}", &vars));
+        assert_eq!("notif|notifications", render("{s:.5}|{s:5}", &vars));
+        assert_eq!("notif     |", render("{s:10.5}|", &vars));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("{unclosed".parse::<Template>().is_err());
+        assert!("{}".parse::<Template>().is_err());
+        assert!("{n:abc}".parse::<Template>().is_err());
+        assert!("{n:.x}".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Tree is a temporary directory of files for a test, such as a fake copy of
+/// part of sysfs. It's removed when it's dropped, even if the test fails.
+#[derive(Debug)]
+pub struct Tree(PathBuf);
+
+impl Tree {
+    /// Creates an empty directory for the test with the given name, which must
+    /// be unique within the test binary.
+    pub fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("waybar-{}-{}", name, std::process::id()));
+
+        // Anything left here is from an earlier run that was killed.
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        Self(root)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.0.join(path)
+    }
+
+    /// Writes a file within the tree, creating any directories it needs.
+    pub fn write<P: AsRef<Path>>(&self, path: P, content: &str) {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+}
+
+impl Drop for Tree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{Output, Percentage};
+
+/// The state of a value relative to its thresholds.
+#[derive(Debug, Default, Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+pub enum State {
+    #[default]
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl State {
+    /// Returns the CSS class for the state, if any.
+    pub fn class(&self) -> Option<&'static str> {
+        match self {
+            State::Normal => None,
+            State::Warning => Some("warning"),
+            State::Critical => Some("critical"),
+        }
+    }
+}
+
+/// Thresholds tracks whether a percentage has crossed the warning or critical
+/// thresholds, in the same spirit as the states supported by Waybar's built in
+/// modules.
+///
+/// A state is entered as soon as the value reaches its threshold, but is only
+/// left once the value drops more than the hysteresis below it. This keeps a
+/// value that's hovering around a threshold from making the bar flicker.
+#[derive(Debug, Default)]
+pub struct Thresholds {
+    warning: Option<u8>,
+    critical: Option<u8>,
+    hysteresis: u8,
+    state: State,
+}
+
+impl Thresholds {
+    pub fn new(warning: Option<u8>, critical: Option<u8>, hysteresis: u8) -> Self {
+        Self {
+            warning,
+            critical,
+            hysteresis,
+            state: State::Normal,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Updates the state with a new value, and returns the new state.
+    pub fn update(&mut self, perc: Percentage) -> State {
+        let value = perc.as_u8();
+        let raw = self.level(value);
+
+        self.state = if raw > self.state {
+            raw
+        } else {
+            self.state
+                .min(self.level(value.saturating_add(self.hysteresis)))
+        };
+
+        self.state
+    }
+
+    /// Updates the state with a new value, and adds the class for the new
+    /// state (if any) to the output.
+    pub fn apply(&mut self, perc: Percentage, output: &mut Output) {
+        if let Some(class) = self.update(perc).class() {
+            output.class.push(class.into());
+        }
+    }
+
+    fn level(&self, value: u8) -> State {
+        let reached = |threshold: Option<u8>| matches!(threshold, Some(t) if value >= t);
+
+        if reached(self.critical) {
+            State::Critical
+        } else if reached(self.warning) {
+            State::Warning
+        } else {
+            State::Normal
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(thresholds: &mut Thresholds, value: f64) -> State {
+        thresholds.update(Percentage::from(value / 100.))
+    }
+
+    #[test]
+    fn test_without_thresholds() {
+        let mut thresholds = Thresholds::default();
+
+        assert_eq!(State::Normal, update(&mut thresholds, 0.));
+        assert_eq!(State::Normal, update(&mut thresholds, 100.));
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut thresholds = Thresholds::new(Some(70), Some(90), 5);
+
+        assert_eq!(State::Normal, update(&mut thresholds, 69.));
+        assert_eq!(State::Warning, update(&mut thresholds, 70.));
+        assert_eq!(State::Warning, update(&mut thresholds, 66.));
+        assert_eq!(State::Critical, update(&mut thresholds, 95.));
+        assert_eq!(State::Critical, update(&mut thresholds, 86.));
+        assert_eq!(State::Warning, update(&mut thresholds, 84.));
+        assert_eq!(State::Normal, update(&mut thresholds, 64.));
+    }
+
+    #[test]
+    fn test_falls_straight_through() {
+        let mut thresholds = Thresholds::new(Some(70), Some(90), 5);
+
+        assert_eq!(State::Critical, update(&mut thresholds, 100.));
+        assert_eq!(State::Normal, update(&mut thresholds, 10.));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut thresholds = Thresholds::new(None, Some(90), 0);
+        let mut output = Output {
+            class: vec!["mem".into()],
+            ..Output::default()
+        };
+
+        thresholds.apply(Percentage::from(0.95), &mut output);
+        assert_eq!(vec!["mem", "critical"], output.class);
+    }
+}
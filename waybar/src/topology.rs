@@ -0,0 +1,358 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Cluster is a group of CPUs of the same type, such as the performance cores
+/// on a hybrid CPU.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    /// A short name, suitable for use on the command line and in template
+    /// placeholders.
+    pub name: String,
+
+    /// A name suitable for display in a tooltip.
+    pub label: String,
+
+    pub cpus: Vec<usize>,
+}
+
+impl Cluster {
+    pub fn contains(&self, cpu: usize) -> bool {
+        self.cpus.contains(&cpu)
+    }
+}
+
+/// Topology groups the CPUs into clusters of the same type.
+///
+/// Intel hybrid CPUs are detected through their separate `cpu_core` and
+/// `cpu_atom` PMUs, giving `p` and `e` clusters. Otherwise, CPUs are grouped
+/// by `cpu_capacity`, as found on ARM big.LITTLE systems: two groups become `p`
+/// and `e`, and any other number become `cluster0` (the most capable) and up.
+/// On non-x86 systems that don't report capacity, `topology/cluster_id` is
+/// used instead, as long as every CPU has one; on x86, that only describes
+/// which cores share an L2 cache, so it isn't a useful grouping.
+///
+/// If none of those distinguish any CPUs, there's a single `all` cluster. If
+/// there's no CPU sysfs at all, there are no clusters, and asking for one is an
+/// error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topology {
+    clusters: Vec<Cluster>,
+}
+
+impl Topology {
+    pub fn new(clusters: Vec<Cluster>) -> Self {
+        Self { clusters }
+    }
+
+    pub fn read() -> Result<Self> {
+        Self::read_from(Path::new("/sys/devices/system/cpu"))
+    }
+
+    /// Reads the topology from the given CPU sysfs directory, which is
+    /// normally `/sys/devices/system/cpu`. The hybrid PMUs are looked for two
+    /// levels up, where they'd be in `/sys/devices`.
+    pub fn read_from(path: &Path) -> Result<Self> {
+        let devices = path.join("../..");
+        if let (Some(core), Some(atom)) = (
+            optional(&devices.join("cpu_core/cpus"))?,
+            optional(&devices.join("cpu_atom/cpus"))?,
+        ) {
+            return Ok(Self {
+                clusters: vec![
+                    cluster("p", "P-cores", parse_cpu_list(&core)?),
+                    cluster("e", "E-cores", parse_cpu_list(&atom)?),
+                ],
+            });
+        }
+
+        // Without a CPU sysfs at all, we don't even know which CPUs there are,
+        // so there's nothing to group.
+        let dir = match path.read_dir() {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Self {
+                    clusters: Vec::new(),
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut cpus = Vec::new();
+        for entry in dir {
+            let entry = entry?;
+            if let Some(id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("cpu"))
+                .and_then(|id| id.parse::<usize>().ok())
+            {
+                let capacity = optional(&entry.path().join("cpu_capacity"))?;
+                let cluster_id = optional(&entry.path().join("topology/cluster_id"))?;
+                cpus.push((id, capacity, cluster_id));
+            }
+        }
+        cpus.sort_by_key(|(id, _, _)| *id);
+        let all: Vec<usize> = cpus.iter().map(|(id, _, _)| *id).collect();
+
+        // BTreeMaps keep the groups in a stable order.
+        let mut capacities: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+        for (id, capacity, _) in cpus.iter() {
+            if let Some(capacity) = capacity {
+                capacities
+                    .entry(capacity.trim().parse()?)
+                    .or_default()
+                    .push(*id);
+            }
+        }
+
+        let cluster_ids = by_cluster_id(&cpus)?;
+
+        let clusters = if capacities.len() > 1 {
+            // Most capable first.
+            let groups: Vec<Vec<usize>> =
+                capacities.into_iter().rev().map(|(_, cpus)| cpus).collect();
+            if groups.len() == 2 {
+                vec![
+                    cluster("p", "P-cores", groups[0].clone()),
+                    cluster("e", "E-cores", groups[1].clone()),
+                ]
+            } else {
+                numbered(groups.into_iter().enumerate())
+            }
+        } else if capacities.is_empty()
+            && cluster_ids.len() > 1
+            && !cfg!(any(target_arch = "x86", target_arch = "x86_64"))
+        {
+            numbered(cluster_ids.into_iter())
+        } else {
+            vec![cluster("all", "All cores", all)]
+        };
+
+        Ok(Self { clusters })
+    }
+
+    pub fn clusters(&self) -> &[Cluster] {
+        &self.clusters
+    }
+
+    /// Returns true if there's more than one type of CPU.
+    pub fn is_hybrid(&self) -> bool {
+        self.clusters.len() > 1
+    }
+
+    /// Returns the cluster with the given name, or an error listing the
+    /// clusters that do exist.
+    pub fn cluster(&self, name: &str) -> Result<&Cluster> {
+        if self.clusters.is_empty() {
+            return Err(anyhow!(
+                "unknown cluster {}: no CPUs were found in sysfs",
+                name
+            ));
+        }
+
+        self.clusters
+            .iter()
+            .find(|cluster| cluster.name == name)
+            .ok_or_else(|| {
+                anyhow!(
+                    "unknown cluster {}: expected {}",
+                    name,
+                    self.clusters
+                        .iter()
+                        .map(|cluster| cluster.name.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(", ")
+                )
+            })
+    }
+
+    /// Returns the cluster that a CPU belongs to, if any.
+    pub fn cluster_of(&self, cpu: usize) -> Option<&Cluster> {
+        self.clusters.iter().find(|cluster| cluster.contains(cpu))
+    }
+}
+
+fn cluster(name: &str, label: &str, cpus: Vec<usize>) -> Cluster {
+    Cluster {
+        name: name.into(),
+        label: label.into(),
+        cpus,
+    }
+}
+
+/// Groups CPUs by their `topology/cluster_id`. The kernel reports -1 if the
+/// firmware doesn't describe clusters, so if any CPU doesn't have a valid
+/// ID, there's no grouping at all.
+fn by_cluster_id(
+    cpus: &[(usize, Option<String>, Option<String>)],
+) -> Result<BTreeMap<usize, Vec<usize>>> {
+    let mut cluster_ids: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (id, _, cluster_id) in cpus.iter() {
+        let cluster_id: i64 = match cluster_id {
+            Some(cluster_id) => cluster_id.trim().parse()?,
+            None => return Ok(BTreeMap::new()),
+        };
+        if cluster_id < 0 {
+            return Ok(BTreeMap::new());
+        }
+
+        cluster_ids
+            .entry(cluster_id as usize)
+            .or_default()
+            .push(*id);
+    }
+
+    Ok(cluster_ids)
+}
+
+fn numbered<I>(groups: I) -> Vec<Cluster>
+where
+    I: Iterator<Item = (usize, Vec<usize>)>,
+{
+    groups
+        .map(|(n, cpus)| cluster(&format!("cluster{}", n), &format!("Cluster {}", n), cpus))
+        .collect()
+}
+
+/// Reads a file that might not exist.
+pub(crate) fn optional(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(Some(s)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parses a list of CPUs like `0-3,8,10-11`, as used throughout sysfs.
+pub(crate) fn parse_cpu_list(s: &str) -> Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+
+    for range in s.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => {
+                let (start, end): (usize, usize) = (start.parse()?, end.parse()?);
+                if end < start {
+                    return Err(anyhow!("invalid CPU range {:?}", range));
+                }
+                cpus.extend(start..=end);
+            }
+            None => cpus.push(range.parse()?),
+        }
+    }
+
+    Ok(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Tree;
+
+    fn cpu(sysfs: &Tree, id: usize, capacity: &str) {
+        sysfs.write(format!("system/cpu/cpu{}/topology/cluster_id", id), "0\n");
+        if !capacity.is_empty() {
+            sysfs.write(format!("system/cpu/cpu{}/cpu_capacity", id), capacity);
+        }
+    }
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(
+            vec![0, 1, 2, 3, 8, 10, 11],
+            parse_cpu_list("0-3,8,10-11\n").unwrap()
+        );
+        assert!(parse_cpu_list("").unwrap().is_empty());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+    }
+
+    #[test]
+    fn test_intel_hybrid() {
+        let sysfs = Tree::new("topology-intel");
+        cpu(&sysfs, 0, "");
+        sysfs.write("cpu_core/cpus", "0-3\n");
+        sysfs.write("cpu_atom/cpus", "4-11\n");
+
+        let topology = Topology::read_from(&sysfs.join("system/cpu")).unwrap();
+        assert!(topology.is_hybrid());
+        assert_eq!(vec![0, 1, 2, 3], topology.cluster("p").unwrap().cpus);
+        assert_eq!("E-cores", topology.cluster_of(7).unwrap().label);
+        assert_eq!(
+            "unknown cluster x: expected p, e",
+            topology.cluster("x").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_capacity() {
+        let sysfs = Tree::new("topology-capacity");
+        cpu(&sysfs, 0, "446\n");
+        cpu(&sysfs, 1, "446\n");
+        cpu(&sysfs, 2, "1024\n");
+        cpu(&sysfs, 3, "1024\n");
+
+        let topology = Topology::read_from(&sysfs.join("system/cpu")).unwrap();
+        assert_eq!(vec![2, 3], topology.cluster("p").unwrap().cpus);
+        assert_eq!(vec![0, 1], topology.cluster("e").unwrap().cpus);
+
+        cpu(&sysfs, 4, "871\n");
+        let topology = Topology::read_from(&sysfs.join("system/cpu")).unwrap();
+        assert_eq!(
+            vec!["cluster0", "cluster1", "cluster2"],
+            topology
+                .clusters()
+                .iter()
+                .map(|cluster| cluster.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec![4], topology.cluster("cluster1").unwrap().cpus);
+    }
+
+    #[test]
+    fn test_uniform() {
+        let sysfs = Tree::new("topology-uniform");
+        cpu(&sysfs, 0, "1024\n");
+        cpu(&sysfs, 1, "1024\n");
+        cpu(&sysfs, 10, "");
+
+        let topology = Topology::read_from(&sysfs.join("system/cpu")).unwrap();
+        assert!(!topology.is_hybrid());
+        assert_eq!(vec![0, 1, 10], topology.cluster("all").unwrap().cpus);
+
+        let topology = Topology::read_from(&sysfs.join("missing")).unwrap();
+        assert!(!topology.is_hybrid());
+        assert!(topology.clusters().is_empty());
+        assert_eq!(
+            "unknown cluster all: no CPUs were found in sysfs",
+            topology.cluster("all").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_cluster_id() {
+        let cpus = |ids: &[&str]| -> Vec<(usize, Option<String>, Option<String>)> {
+            ids.iter()
+                .enumerate()
+                .map(|(id, cluster_id)| {
+                    (
+                        id,
+                        None,
+                        Some(cluster_id.to_string()).filter(|s| !s.is_empty()),
+                    )
+                })
+                .collect()
+        };
+
+        let groups = by_cluster_id(&cpus(&["0\n", "0\n", "1\n"])).unwrap();
+        assert_eq!(vec![0, 1], groups[&0]);
+        assert_eq!(vec![2], groups[&1]);
+
+        assert!(by_cluster_id(&cpus(&["-1\n", "-1\n"])).unwrap().is_empty());
+        assert!(by_cluster_id(&cpus(&["0\n", "-1\n"])).unwrap().is_empty());
+        assert!(by_cluster_id(&cpus(&["0\n", ""])).unwrap().is_empty());
+        assert!(by_cluster_id(&cpus(&["x"])).is_err());
+    }
+}
@@ -0,0 +1,20 @@
+use std::sync::mpsc::Sender;
+
+use anyhow::Result;
+
+use crate::Event;
+
+/// Trigger is a cloneable handle that can be used to request that a Loop
+/// updates its module outside of the normal interval.
+#[derive(Debug, Clone)]
+pub struct Trigger(Sender<Event>);
+
+impl Trigger {
+    pub(crate) fn new(sender: Sender<Event>) -> Self {
+        Self(sender)
+    }
+
+    pub fn refresh(&self) -> Result<()> {
+        Ok(self.0.send(Event::Refresh)?)
+    }
+}
@@ -1,9 +1,11 @@
+use anyhow::{anyhow, Error};
 use std::collections::vec_deque::{Iter, VecDeque};
 use std::fmt::Display;
+use std::str::FromStr;
 
 enum HistoryType<T> {
     Single(T),
-    Multiple(BoundedDeque<T>),
+    Multiple(BoundedDeque<Option<T>>),
 }
 
 pub struct History<T>(HistoryType<T>);
@@ -29,7 +31,16 @@ impl<T> History<T> {
                 self.0 = HistoryType::Single(value);
                 Some(old)
             }
-            HistoryType::Multiple(history) => history.push(value),
+            HistoryType::Multiple(history) => history.push(Some(value)).flatten(),
+        }
+    }
+
+    /// Marks a gap in the history, such as when the system was suspended, so
+    /// that a sparkline doesn't join up values either side of it. A single
+    /// value history just keeps its current value.
+    pub fn push_gap(&mut self) {
+        if let HistoryType::Multiple(history) = &mut self.0 {
+            history.push(None);
         }
     }
 
@@ -37,21 +48,207 @@ impl<T> History<T> {
     where
         T: Ord + Display,
         F: Fn(&T) -> u8,
+    {
+        self.render(&Sparks, norm)
+    }
+
+    /// Renders the history with the given renderer. norm must map each value
+    /// onto the range 0-100; anything higher will be clamped.
+    pub fn render<R, F>(&self, renderer: &R, norm: F) -> String
+    where
+        T: Ord + Display,
+        R: Renderer + ?Sized,
+        F: Fn(&T) -> u8,
     {
         match &self.0 {
             HistoryType::Single(v) => format!("{}", v),
-            HistoryType::Multiple(history) => format!(
-                "{{{}}}",
-                history
+            HistoryType::Multiple(history) => renderer.render_with_gaps(
+                &history
                     .iter()
-                    .map(|v| format!("{}", norm(v).max(0).min(100)))
-                    .collect::<Vec<String>>()
-                    .join(",")
+                    .map(|v| v.as_ref().map(|v| norm(v).min(100)))
+                    .collect::<Vec<Option<u8>>>(),
             ),
         }
     }
 }
 
+/// Renderer turns a series of values between 0 and 100 into a sparkline.
+pub trait Renderer {
+    fn render(&self, values: &[u8]) -> String;
+
+    /// Renders a series that may have gaps, represented by None. By default,
+    /// gaps are rendered as zeroes, but the built in renderers all override
+    /// this to leave a blank space.
+    fn render_with_gaps(&self, values: &[Option<u8>]) -> String {
+        self.render(
+            &values
+                .iter()
+                .map(|v| v.unwrap_or_default())
+                .collect::<Vec<u8>>(),
+        )
+    }
+}
+
+/// Sparks renders values in the `{a,b,c}` syntax that the [Sparks
+/// font](https://github.com/aftertheflood/sparks) turns into a chart. Gaps
+/// split the chart in two, with a space for each missing value.
+#[derive(Debug, Clone, Copy)]
+pub struct Sparks;
+
+impl Renderer for Sparks {
+    fn render(&self, values: &[u8]) -> String {
+        format!(
+            "{{{}}}",
+            values
+                .iter()
+                .map(|v| format!("{}", v))
+                .collect::<Vec<String>>()
+                .join(",")
+        )
+    }
+
+    fn render_with_gaps(&self, values: &[Option<u8>]) -> String {
+        if values.iter().all(Option::is_some) {
+            return self.render(&values.iter().flatten().copied().collect::<Vec<u8>>());
+        }
+
+        let mut rendered = String::new();
+        let mut run = Vec::new();
+        for v in values {
+            match v {
+                Some(v) => run.push(*v),
+                None => {
+                    if !run.is_empty() {
+                        rendered.push_str(&self.render(&run));
+                        run.clear();
+                    }
+                    rendered.push(' ');
+                }
+            }
+        }
+        if !run.is_empty() {
+            rendered.push_str(&self.render(&run));
+        }
+
+        rendered
+    }
+}
+
+/// Blocks renders one Unicode block element per value, which works in any
+/// font with reasonable Unicode coverage. Gaps are rendered as spaces.
+#[derive(Debug, Clone, Copy)]
+pub struct Blocks;
+
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+impl Renderer for Blocks {
+    fn render(&self, values: &[u8]) -> String {
+        self.render_with_gaps(&values.iter().copied().map(Some).collect::<Vec<_>>())
+    }
+
+    fn render_with_gaps(&self, values: &[Option<u8>]) -> String {
+        values
+            .iter()
+            .map(|v| match v {
+                Some(v) => BLOCKS[scale(*v, BLOCKS.len() - 1)],
+                None => ' ',
+            })
+            .collect()
+    }
+}
+
+/// Braille renders two values per glyph as columns of braille dots, which is
+/// denser than Blocks at the cost of resolution. Gaps are rendered as empty
+/// columns.
+#[derive(Debug, Clone, Copy)]
+pub struct Braille;
+
+// Dot bits for each column, from the bottom row up.
+const BRAILLE_LEFT: [u32; 4] = [0x40, 0x04, 0x02, 0x01];
+const BRAILLE_RIGHT: [u32; 4] = [0x80, 0x20, 0x10, 0x08];
+
+impl Renderer for Braille {
+    fn render(&self, values: &[u8]) -> String {
+        self.render_with_gaps(&values.iter().copied().map(Some).collect::<Vec<_>>())
+    }
+
+    fn render_with_gaps(&self, values: &[Option<u8>]) -> String {
+        // Like Blocks, the bottom row is always filled so that a zero value
+        // is still visible as a baseline.
+        let column = |dots: &[u32; 4], v: Option<u8>| -> u32 {
+            dots.iter()
+                .take(v.map(|v| 1 + scale(v, dots.len() - 1)).unwrap_or(0))
+                .sum()
+        };
+
+        // If there's an odd number of values, we'll pad the oldest end so the
+        // newest value always ends up in the rightmost column.
+        let mut padded: Vec<Option<u8>> = Vec::with_capacity(values.len() + 1);
+        if values.len() % 2 == 1 {
+            padded.push(None);
+        }
+        padded.extend(values.iter().copied());
+
+        padded
+            .chunks(2)
+            .map(|pair| {
+                std::char::from_u32(
+                    0x2800 + column(&BRAILLE_LEFT, pair[0]) + column(&BRAILLE_RIGHT, pair[1]),
+                )
+                .unwrap_or(' ')
+            })
+            .collect()
+    }
+}
+
+/// Scales a value between 0 and 100 to the nearest step between 0 and max.
+fn scale(v: u8, max: usize) -> usize {
+    (usize::from(v.min(100)) * max + 50) / 100
+}
+
+/// Style selects one of the built in renderers, typically from the command
+/// line.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Style {
+    Sparks,
+    Blocks,
+    Braille,
+}
+
+impl Renderer for Style {
+    fn render(&self, values: &[u8]) -> String {
+        match self {
+            Style::Sparks => Sparks.render(values),
+            Style::Blocks => Blocks.render(values),
+            Style::Braille => Braille.render(values),
+        }
+    }
+
+    fn render_with_gaps(&self, values: &[Option<u8>]) -> String {
+        match self {
+            Style::Sparks => Sparks.render_with_gaps(values),
+            Style::Blocks => Blocks.render_with_gaps(values),
+            Style::Braille => Braille.render_with_gaps(values),
+        }
+    }
+}
+
+impl FromStr for Style {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sparks" => Ok(Style::Sparks),
+            "blocks" => Ok(Style::Blocks),
+            "braille" => Ok(Style::Braille),
+            _ => Err(anyhow!(
+                "unknown sparkline style {}: expected sparks, blocks, or braille",
+                s
+            )),
+        }
+    }
+}
+
 struct BoundedDeque<T> {
     queue: VecDeque<T>,
     capacity: usize,
@@ -76,7 +273,66 @@ impl<T> BoundedDeque<T> {
         }
     }
 
-    fn iter(&self) -> Iter<T> {
+    fn iter(&self) -> Iter<'_, T> {
         self.queue.iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparks() {
+        assert_eq!("{0,50,100}", Sparks.render(&[0, 50, 100]));
+        assert_eq!("{}", Sparks.render(&[]));
+        assert_eq!("{}", Sparks.render_with_gaps(&[]));
+        assert_eq!(
+            "  {10,20} {30}",
+            Sparks.render_with_gaps(&[None, None, Some(10), Some(20), None, Some(30)])
+        );
+        assert_eq!("{10} ", Sparks.render_with_gaps(&[Some(10), None]));
+    }
+
+    #[test]
+    fn test_blocks() {
+        assert_eq!("▁▄▅█", Blocks.render(&[0, 45, 55, 100]));
+    }
+
+    #[test]
+    fn test_braille() {
+        assert_eq!("\u{28c0}\u{28ff}", Braille.render(&[0, 0, 100, 100]));
+        assert_eq!("\u{28a0}\u{28c6}", Braille.render(&[25, 75, 0]));
+    }
+
+    #[test]
+    fn test_render() {
+        let mut history = History::new(3);
+        history.push(10u8);
+        history.push(120u8);
+
+        assert_eq!("{10,100}", history.to_string(|v| *v));
+        assert_eq!("▂█", history.render(&Style::Blocks, |v| *v));
+
+        let mut single = History::new(1);
+        single.push(42u8);
+        assert_eq!("42", single.render(&Style::Braille, |v| *v));
+    }
+
+    #[test]
+    fn test_gaps() {
+        let mut history = History::new(4);
+        history.push(100u8);
+        history.push_gap();
+        history.push(0u8);
+
+        assert_eq!("{100} {0}", history.render(&Sparks, |v| *v));
+        assert_eq!("█ ▁", history.render(&Blocks, |v| *v));
+        assert_eq!("\u{28b8}\u{2880}", history.render(&Braille, |v| *v));
+
+        let mut single = History::new(1);
+        single.push(42u8);
+        single.push_gap();
+        assert_eq!("42", single.render(&Blocks, |v| *v));
+    }
+}
@@ -1,12 +1,11 @@
 use std::ffi::OsString;
+use std::thread;
 
 use anyhow::Result;
 use humantime::Duration;
-use miniserde::json;
-
+use nix::sys::signal::Signal;
 use structopt::StructOpt;
-use tokio::{runtime::Runtime, signal::unix::SignalKind, sync::mpsc};
-use waybar::Output;
+use waybar::{Debouncer, Event, Loop, Module, Output, OutputOpt, Template, Trigger, Vars};
 
 mod tasks;
 mod udev;
@@ -42,6 +41,16 @@ struct Opt {
     )]
     not_found: String,
 
+    #[structopt(long, default_value = "{icon}", help = "format for the module text")]
+    format: Template,
+
+    #[structopt(
+        long,
+        default_value = "Camera {status}",
+        help = "format for the tooltip"
+    )]
+    tooltip_format: Template,
+
     #[structopt(
         short,
         long,
@@ -50,24 +59,23 @@ struct Opt {
         help = "subsystem to look for webcams on"
     )]
     subsystem: OsString,
+
+    #[structopt(flatten)]
+    output: OutputOpt,
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
 
-    // We want a manual runtime because we need to able to create a blocking
-    // task, which the high level API doesn't support.
-    let rt = Runtime::new()?;
-
-    // OK, so we're going to set up a few tasks. Basically, we are going to:
+    // OK, so the loop is going to be fed by a few sources. Basically:
     //
-    // 1. Read from the raw udev monitor. This is extremely noisy.
-    // 2. Debounce those inputs into an update channel that only fires after
-    //    udev has had a chance to settle.
-    // 3. Install a SIGUSR1 handler that also sends events to the update
-    //    channel.
-    // 4. Listen on the update channel and then interrogate udev to find out if
-    //    there's a video device attached.
+    // 1. A thread reads from the raw udev monitor. This is extremely noisy.
+    // 2. A debouncer coalesces those inputs into a single refresh that only
+    //    fires after udev has had a chance to settle.
+    // 3. A SIGUSR1 handler also requests a refresh.
+    //
+    // Each refresh then interrogates udev to find out if there's a video
+    // device attached.
     //
     // You may ask why we don't just track the state of devices from the
     // updates. Theoretically, this would work, but we'd have to retain the
@@ -76,80 +84,100 @@ fn main() -> Result<()> {
     // for the current devices on startup anyway, so we may as well use the same
     // logic on update and keep this as stateless as possible. udev is pretty
     // efficient, and this is reasonably cheap in practice.
-    //
-    // Note that we're careful not to pass opt directly into any of these blocks
-    // or closures until the last one: since we'll need some fields but not
-    // others, the tasks::* types have been designed to take clones or copies of
-    // those fields as appropriate outside of the spawned task.
-
-    let (raw_tx, raw_rx) = mpsc::unbounded_channel();
-    let (update_tx, mut update_rx) = mpsc::unbounded_channel();
-
-    // Set up the monitor adapter to get raw udev events and notify the
-    // debouncer when they're received. Note that this needs to be on a blocking
-    // pool thread because the underlying API is not async.
-    let mut monitor = tasks::MonitorAdapter::new(raw_tx, &opt.subsystem);
-    rt.spawn_blocking(move || monitor.block());
-
-    // Set up the debouncer.
-    let mut debouncer = tasks::Debouncer::new(raw_rx, update_tx.clone(), opt.debounce.into());
-    rt.spawn(async move { Ok::<(), anyhow::Error>(debouncer.process().await?) });
-
-    // Set up the signal handler.
-    let signal_handler = tasks::SignalHandler::new(update_tx.clone(), SignalKind::user_defined1());
-    rt.spawn(async move { Ok::<(), anyhow::Error>(signal_handler.process().await?) });
-
-    // Immediately queue an update.
-    update_tx.send(())?;
-
-    // Finally, block the runtime on the last task, which receives update
-    // requests on update_rx, checks if any video devices are attached, and
-    // prints the appropriate output for waybar to consume.
-    Ok(rt.block_on(async move {
-        let mut checker = Checker::new(&opt.subsystem, &opt.driver)?;
-        let formatter = Formatter {
+    let webcam = Loop::on_demand(Webcam {
+        checker: Checker::new(&opt.subsystem, &opt.driver)?,
+        debounce: opt.debounce.into(),
+        subsystem: opt.subsystem,
+        formatter: Formatter {
             found: opt.found,
             not_found: opt.not_found,
-        };
+            format: opt.format,
+            tooltip_format: opt.tooltip_format,
+        },
+    });
+    let mut webcam = opt.output.apply(webcam)?;
+    webcam.refresh_on_signal(Signal::SIGUSR1 as i32)?;
+
+    webcam.run()
+}
 
-        loop {
-            while let Some(_) = update_rx.recv().await {
-                formatter.output(checker.has_devices()?);
-            }
-        }
+struct Webcam {
+    checker: Checker,
+    debounce: std::time::Duration,
+    formatter: Formatter,
+    subsystem: OsString,
+}
 
-        // This is unreachable, but required to set the return type of the block
-        // and therefore allow the ? operator to work, since Rust cannot
-        // currently infer the return type of anonymous async blocks.
-        #[allow(unreachable_code)]
-        Ok::<(), anyhow::Error>(())
-    })?)
+impl Module for Webcam {
+    fn start(&mut self, trigger: &Trigger) -> Result<()> {
+        // Set up the monitor adapter to get raw udev events and notify the
+        // debouncer when they're received. This gets its own thread because
+        // the underlying API is blocking.
+        let debouncer = Debouncer::new(trigger.clone(), self.debounce);
+        let mut monitor = tasks::MonitorAdapter::new(debouncer, &self.subsystem);
+        thread::spawn(move || monitor.block());
+
+        Ok(())
+    }
+
+    fn update(&mut self, _event: Event) -> Result<Output> {
+        Ok(self.formatter.output(self.checker.has_devices()?))
+    }
 }
 
 struct Formatter {
     found: String,
     not_found: String,
+    format: Template,
+    tooltip_format: Template,
 }
 
 impl Formatter {
-    fn output(&self, found: bool) {
-        println!(
-            "{}",
-            json::to_string(&if found {
-                Output {
-                    tooltip: "Camera connected".into(),
-                    class: vec!["found".into()],
-                    percentage: 100,
-                    text: self.found.clone(),
-                }
-            } else {
-                Output {
-                    tooltip: "Camera not connected".into(),
-                    class: vec!["not-found".into()],
-                    percentage: 0,
-                    text: self.not_found.clone(),
-                }
-            })
-        )
+    fn output(&self, found: bool) -> Output {
+        let vars = if found {
+            Vars::new()
+                .with("icon", self.found.as_str())
+                .with("status", "connected")
+        } else {
+            Vars::new()
+                .with("icon", self.not_found.as_str())
+                .with("status", "not connected")
+        };
+
+        let state = if found { "found" } else { "not-found" };
+
+        Output {
+            text: self.format.render(&vars),
+            alt: Some(state.into()),
+            tooltip: self.tooltip_format.render_optional(&vars),
+            class: vec![state.into()],
+            percentage: Some(if found { 100 } else { 0 }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output() {
+        let mut formatter = Formatter {
+            found: "on".into(),
+            not_found: "off".into(),
+            format: "{icon}".parse().unwrap(),
+            tooltip_format: "Webcam {status}".parse().unwrap(),
+        };
+
+        let output = formatter.output(true);
+        assert_eq!("on", output.text);
+        assert_eq!(Some("Webcam connected".to_string()), output.tooltip);
+
+        // An empty tooltip is left out, rather than sent as an empty string.
+        formatter.tooltip_format = "".parse().unwrap();
+        assert_eq!(
+            r#"{"alt":"not-found","class":["not-found"],"percentage":0,"text":"off"}"#,
+            formatter.output(false).to_json()
+        );
     }
 }
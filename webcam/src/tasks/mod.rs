@@ -1,8 +1,2 @@
-mod debounce;
-pub(crate) use debounce::Debouncer;
-
-mod signal;
-pub(crate) use signal::SignalHandler;
-
 mod udev;
 pub(crate) use self::udev::MonitorAdapter;
@@ -1,19 +1,19 @@
 use std::ffi::OsString;
 
 use anyhow::Result;
-use tokio::sync::mpsc::UnboundedSender;
+use waybar::Debouncer;
 
 use crate::udev::Monitor;
 
 pub(crate) struct MonitorAdapter {
-    sender: UnboundedSender<()>,
+    debouncer: Debouncer,
     subsystem: OsString,
 }
 
 impl MonitorAdapter {
-    pub(crate) fn new(sender: UnboundedSender<()>, subsystem: &OsString) -> Self {
+    pub(crate) fn new(debouncer: Debouncer, subsystem: &OsString) -> Self {
         Self {
-            sender,
+            debouncer,
             subsystem: subsystem.clone(),
         }
     }
@@ -23,7 +23,7 @@ impl MonitorAdapter {
 
         loop {
             monitor.block_until_event()?;
-            self.sender.send(())?;
+            self.debouncer.notify()?;
         }
     }
 }